@@ -1,29 +1,83 @@
 use clap::{Parser, Subcommand};
 const DEFAULT_LISTEN_ADDRES: &str = "0.0.0.0:8080";
+const DEFAULT_WEBHOOK_LISTEN_ADDRESS: &str = "0.0.0.0:8443";
+const DEFAULT_WEBHOOK_SERVICE: &str = "port-forward-operator";
+const DEFAULT_WEBHOOK_SECRET: &str = "port-forward-operator-webhook-tls";
+const DEFAULT_METRICS_ADDRESS: &str = "0.0.0.0:9090";
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum SubCommand {
     Controller {
         #[clap(long, env, required = false, default_value = DEFAULT_LISTEN_ADDRES)]
         listen_address: String,
         #[clap(long, env, required = true)]
         image: String,
+        /// Address of the HTTPS listener used for webhooks
+        #[clap(long, env, default_value = DEFAULT_WEBHOOK_LISTEN_ADDRESS)]
+        webhook_listen_address: String,
+        /// Service name the webhook serving certificate is issued for
+        #[clap(long, env, default_value = DEFAULT_WEBHOOK_SERVICE)]
+        webhook_service: String,
+        /// Secret holding the webhook certificates, shared by every replica. It is issued
+        /// and rotated by the controller unless it was created by someone else.
+        #[clap(long, env, default_value = DEFAULT_WEBHOOK_SECRET)]
+        webhook_secret: String,
     },
     Service {
-        #[clap(long, env, required = true)]
-        namespace: String,
-        #[clap(long, env, required = true)]
-        name: String,
-        #[clap(long, env, required = true)]
-        ports: Vec<u16>,
+        #[clap(long, env, required_unless_present = "config")]
+        namespace: Option<String>,
+        /// One of `service`, `pod`, `deployment`, `statefulset`, `selector` or `leader`
+        #[clap(long, env, default_value = "service")]
+        kind: String,
+        #[clap(long, env, required_unless_present_any = ["config", "selector"])]
+        name: Option<String>,
+        /// Pod labels as `key=value,...` for the `selector` and `leader` kinds
+        #[clap(long, env)]
+        selector: Option<String>,
+        /// Seconds connections to a former leader stay open, for the `leader` kind
+        #[clap(long, env, requires = "selector")]
+        drain_seconds: Option<u32>,
+        /// Ports to forward as `local:remote` or `port`
+        #[clap(long, env, required_unless_present = "config")]
+        ports: Vec<String>,
+        /// JSON file listing the services to forward, reloaded when it changes
+        #[clap(long, env, conflicts_with_all = ["namespace", "name", "selector", "drain_seconds", "ports"])]
+        config: Option<String>,
         #[clap(long, env, default_value_t = 3)]
         max_retries: i32,
-        #[clap(long, env, required = true)]
-        kube_context: String,
+        #[clap(long, env)]
+        kube_context: Option<String>,
         #[clap(long, env)]
         kube_user: Option<String>,
         #[clap(long, env)]
         kube_cluster: Option<String>,
+        /// Kubeconfig file that is watched for credential changes
+        #[clap(long, env)]
+        kubeconfig: Option<String>,
+        /// File holding the server url, used instead of a kubeconfig
+        #[clap(long, env, conflicts_with = "kubeconfig")]
+        server_file: Option<String>,
+        #[clap(long, env, requires = "server_file")]
+        certificate_authority_file: Option<String>,
+        #[clap(long, env, requires = "server_file")]
+        token_file: Option<String>,
+        #[clap(long, env, requires = "server_file")]
+        client_certificate_file: Option<String>,
+        #[clap(long, env, requires = "server_file")]
+        client_key_file: Option<String>,
+        /// JSON listing the clusters to fail over to and the health check thresholds
+        #[clap(long, env)]
+        failover: Option<String>,
+        /// JSON listing the clusters new connections are split between and the file
+        /// holding their weights
+        #[clap(long, env, conflicts_with = "failover")]
+        split: Option<String>,
+        /// Seconds between checks of the kubeconfig file
+        #[clap(long, env, default_value_t = 10)]
+        reload_interval: u64,
+        #[clap(long, env, default_value = DEFAULT_METRICS_ADDRESS)]
+        metrics_address: String,
     },
 }
 
@@ -100,19 +154,12 @@ mod tests {
             super::SubCommand::Controller {
                 listen_address,
                 image,
+                ..
             } => {
                 assertions(listen_address, image);
                 Ok(())
             }
-            super::SubCommand::Service {
-                namespace: _,
-                name: _,
-                ports: _,
-                max_retries: _,
-                kube_context: _,
-                kube_user: _,
-                kube_cluster: _,
-            } => Err(Error::IncorrectCommand),
+            super::SubCommand::Service { .. } => Err(Error::IncorrectCommand),
         }
     }
 
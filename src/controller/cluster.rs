@@ -0,0 +1,189 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::{
+    runtime::{
+        controller::Action,
+        metadata_watcher, predicates,
+        reflector::{self, ObjectRef},
+        watcher::{watcher, Config},
+        Controller, WatchStreamExt,
+    },
+    Api, Client, ResourceExt,
+};
+
+use super::{
+    credentials::{self, cluster_prefix, Credentials},
+    error_policy, grant,
+    index::{self, Kind},
+    status, token, Context,
+};
+use crate::{
+    crd::{RemoteCluster, RemoteClusterStatus, SecretReferenceGrant},
+    error::Error,
+};
+
+/// Reconciles `RemoteCluster`s, whose status changes fan out to the forwarded services
+/// referencing them
+pub(crate) async fn run(client: Client, ctx: Arc<Context>) {
+    let api = Api::<RemoteCluster>::all(client.clone());
+    let (reader, writer) = reflector::store();
+    // status writes do not change the generation, so they do not trigger another run
+    let clusters = watcher(api, Config::default().any_semantic())
+        .default_backoff()
+        .reflect(writer)
+        .applied_objects()
+        .predicate_filter(predicates::generation);
+    let controller = Controller::for_stream(clusters, reader);
+    let secret_store = controller.store();
+    let config_map_store = controller.store();
+    let grant_store = controller.store();
+    let secret_index = ctx.cluster_references.clone();
+    let config_map_index = ctx.cluster_references.clone();
+    controller
+        .watches_stream(
+            metadata_watcher(Api::<Secret>::all(client.clone()), Config::default())
+                .touched_objects(),
+            move |secret| secret_index.dependents(Kind::Secret, &secret, &secret_store),
+        )
+        .watches(
+            Api::<SecretReferenceGrant>::all(client.clone()),
+            Config::default(),
+            move |grant| {
+                grant_store
+                    .state()
+                    .into_iter()
+                    .filter(|cluster| {
+                        Credentials::from_connection(
+                            &cluster.spec.connection,
+                            &cluster_prefix(cluster),
+                        )
+                        .map(|c| {
+                            grant::references_namespace(&c, &grant.namespace().unwrap_or_default())
+                        })
+                        .unwrap_or_default()
+                    })
+                    .map(|cluster| ObjectRef::from_obj(cluster.as_ref()))
+                    .collect::<Vec<_>>()
+            },
+        )
+        .watches_stream(
+            metadata_watcher(Api::<ConfigMap>::all(client), Config::default()).touched_objects(),
+            move |config_map| {
+                config_map_index.dependents(Kind::ConfigMap, &config_map, &config_map_store)
+            },
+        )
+        .shutdown_on_signal()
+        .run(reconcile, error_policy, ctx)
+        .filter_map(|x| async move { std::result::Result::ok(x) })
+        .for_each(|_| futures::future::ready(()))
+        .await;
+}
+
+/// Client for the cluster as the controller sees it, or why it cannot be reached
+pub(crate) async fn connect(
+    ctx: &Context,
+    cluster: &RemoteCluster,
+) -> Result<Result<Client, String>, Error> {
+    let credentials =
+        match Credentials::from_connection(&cluster.spec.connection, &cluster_prefix(cluster)) {
+            Ok(credentials) => credentials,
+            Err(message) => return Ok(Err(message)),
+        };
+    let ns = cluster.namespace().unwrap();
+    let inspection = credentials::inspect(ctx.client.clone(), &ns, &credentials).await?;
+    Ok(ctx
+        .clients
+        .get(&ctx.client, &ns, &inspection, &credentials)
+        .await)
+}
+
+async fn reconcile(cluster: Arc<RemoteCluster>, ctx: Arc<Context>) -> Result<Action, Error> {
+    ctx.diagnostics.write().await.last_event = Utc::now();
+    let client = ctx.client.clone();
+    let ns = cluster.namespace().unwrap();
+    let clusters: Api<RemoteCluster> = Api::namespaced(client.clone(), &ns);
+    tracing::info!(
+        "Reconciling RemoteCluster \"{}\" in {}",
+        cluster.name_any(),
+        ns
+    );
+
+    let mut status: RemoteClusterStatus = cluster.status.clone().unwrap_or_default();
+    status.token_expires_at = None;
+    let mut requeue = Duration::from_secs(300);
+    let credentials =
+        Credentials::from_connection(&cluster.spec.connection, &cluster_prefix(&cluster));
+    ctx.cluster_references.set(
+        cluster.as_ref(),
+        credentials
+            .as_ref()
+            .map(|c| index::referenced(c, &ns))
+            .unwrap_or_default(),
+    );
+    let copied = match &credentials {
+        Ok(credentials) => grant::sync(client.clone(), cluster.as_ref(), credentials).await?,
+        Err(_) => Ok(()),
+    };
+    let inspection = match (&credentials, copied) {
+        (Err(message), _) => credentials::Inspection::invalid(None, "Invalid", message.clone()),
+        (Ok(_), Err((reason, message))) => credentials::Inspection::invalid(None, reason, message),
+        (Ok(credentials @ Credentials::TokenRequest { reference, .. }), Ok(())) => {
+            match token::refresh(client.clone(), cluster.as_ref(), &ns, credentials).await? {
+                Ok(expiry) => {
+                    status.token_expires_at = Some(expiry);
+                    requeue = token::requeue_after(reference, expiry, requeue);
+                    credentials::inspect(client.clone(), &ns, credentials).await?
+                }
+                Err((reason, message)) => credentials::Inspection::invalid(None, reason, message),
+            }
+        }
+        (Ok(credentials), Ok(())) => credentials::inspect(client.clone(), &ns, credentials).await?,
+    };
+
+    status.credentials_hash = inspection.hash.clone();
+    let checked = status::check_credentials(
+        inspection.result.clone(),
+        &mut status.conditions,
+        &mut status.expires_at,
+    );
+    let version = match (checked, &credentials) {
+        (Ok(_), Ok(credentials)) => match ctx
+            .clients
+            .get(&client, &ns, &inspection, credentials)
+            .await
+        {
+            Ok(remote) => remote.apiserver_version().await.map_err(|e| e.to_string()),
+            Err(message) => Err(message),
+        },
+        (Err(message), _) => Err(message),
+        (Ok(_), Err(message)) => Err(message.clone()),
+    };
+    match version {
+        Ok(version) => {
+            status.server_version = Some(version.git_version);
+            status::set_condition(
+                &mut status.conditions,
+                status::CONDITION_READY,
+                true,
+                "Reachable",
+                "remote API server is reachable",
+            );
+        }
+        Err(message) => {
+            status.server_version = None;
+            status::set_condition(
+                &mut status.conditions,
+                status::CONDITION_READY,
+                false,
+                "Unreachable",
+                message,
+            );
+            requeue = requeue.min(Duration::from_secs(60));
+        }
+    }
+    status::patch_status(&clusters, cluster.as_ref(), &status).await?;
+    Ok(Action::requeue(requeue))
+}
@@ -0,0 +1,802 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use chrono::{DateTime, TimeZone, Utc};
+
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::{
+    config::{KubeConfigOptions, Kubeconfig},
+    Api, Client, Config, ResourceExt,
+};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+
+use super::tls::certificate_expiry;
+use crate::{
+    crd::{
+        BootstrapCredentials, ClusterConnection, CredentialsReference, ForwardedService,
+        KubeConfigReference, RemoteCluster, TokenRequestReference,
+    },
+    error::Error,
+    kubeconfig::ClusterCredentials,
+};
+
+/// The credential form selected by a `ForwardedService`
+pub(crate) enum Credentials<'a> {
+    /// The forwarder's own service account, for services in the local cluster
+    InCluster,
+    KubeConfig(&'a KubeConfigReference),
+    /// A kubeconfig secret in another namespace, mounted from a copy the controller keeps
+    /// in sync while a `SecretReferenceGrant` allows it
+    CopiedKubeConfig {
+        source: &'a KubeConfigReference,
+        copy: KubeConfigReference,
+    },
+    Structured(&'a CredentialsReference),
+    /// Tokens minted with the bootstrap credentials into a generated secret, which the
+    /// forwarder mounts like structured credentials
+    TokenRequest {
+        reference: &'a TokenRequestReference,
+        bootstrap: Box<Credentials<'a>>,
+        generated: CredentialsReference,
+    },
+}
+
+impl<'a> Credentials<'a> {
+    /// Selects the inline connection settings, or those of the referenced cluster, which
+    /// is `None` when it could not be found
+    pub fn from_spec(
+        fs: &'a ForwardedService,
+        cluster: Option<&'a RemoteCluster>,
+    ) -> Result<Self, String> {
+        match (&fs.spec.cluster_ref, cluster) {
+            (None, _) => Self::from_connection(&fs.spec.connection, &fs.name_any()),
+            (Some(_), _) if !fs.spec.connection.is_empty() => {
+                Err("`cluster_ref` cannot be combined with inline connection settings".to_owned())
+            }
+            (Some(name), Some(cluster)) if cluster.name_any() == *name => {
+                Self::from_connection(&cluster.spec.connection, &cluster_prefix(cluster))
+            }
+            (Some(name), _) => Err(format!("remote cluster `{name}` not found")),
+        }
+    }
+
+    /// Secrets generated for these credentials, such as minted tokens or copies from other
+    /// namespaces, are named after `prefix`
+    pub fn from_connection(
+        connection: &'a ClusterConnection,
+        prefix: &str,
+    ) -> Result<Self, String> {
+        match (
+            &connection.kube_config,
+            &connection.credentials,
+            &connection.token_request,
+        ) {
+            (Some(reference), None, None) => Ok(Self::from_kube_config(
+                reference,
+                format!("{prefix}-kubeconfig"),
+            )),
+            (None, Some(reference), None) => Ok(Self::Structured(reference)),
+            (None, None, Some(reference)) => Ok(Self::TokenRequest {
+                reference,
+                bootstrap: Box::new(Self::from_bootstrap(&reference.bootstrap, prefix)?),
+                generated: CredentialsReference {
+                    secret: format!("{prefix}-token"),
+                    ..Default::default()
+                },
+            }),
+            (None, None, None) => Ok(Self::InCluster),
+            _ => Err(
+                "only one of `kube_config`, `credentials` and `token_request` may be set"
+                    .to_owned(),
+            ),
+        }
+    }
+
+    fn from_kube_config(reference: &'a KubeConfigReference, copy: String) -> Self {
+        match &reference.namespace {
+            Some(_) => Self::CopiedKubeConfig {
+                source: reference,
+                copy: KubeConfigReference {
+                    secret: copy,
+                    namespace: None,
+                    ..reference.clone()
+                },
+            },
+            None => Self::KubeConfig(reference),
+        }
+    }
+
+    fn from_bootstrap(bootstrap: &'a BootstrapCredentials, prefix: &str) -> Result<Self, String> {
+        match (&bootstrap.kube_config, &bootstrap.credentials) {
+            (Some(reference), None) => Ok(Self::from_kube_config(
+                reference,
+                format!("{prefix}-bootstrap"),
+            )),
+            (None, Some(reference)) => Ok(Self::Structured(reference)),
+            _ => Err(
+                "`token_request.bootstrap` needs exactly one of `kube_config` or `credentials`"
+                    .to_owned(),
+            ),
+        }
+    }
+
+    /// Secret mounted into the forwarder
+    pub fn secret(&self) -> Option<&str> {
+        match self {
+            Self::InCluster => None,
+            Self::KubeConfig(reference) => Some(&reference.secret),
+            Self::CopiedKubeConfig { copy, .. } => Some(&copy.secret),
+            Self::Structured(reference) => Some(&reference.secret),
+            Self::TokenRequest { generated, .. } => Some(&generated.secret),
+        }
+    }
+
+    /// Config map mounted into the forwarder
+    pub fn config_map(&self) -> Option<&str> {
+        match self {
+            Self::Structured(reference) => reference.config_map.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Kubeconfig reference mounted into the forwarder
+    pub fn kube_config(&self) -> Option<&KubeConfigReference> {
+        match self {
+            Self::KubeConfig(reference) => Some(reference),
+            Self::CopiedKubeConfig { copy, .. } => Some(copy),
+            _ => None,
+        }
+    }
+
+    /// Kubeconfig secrets in other namespaces, including those of the bootstrap
+    /// credentials, as namespace, name and the copy kept next to the referencing object
+    pub fn copies(&self) -> Vec<(&str, &str, &str)> {
+        match self {
+            Self::CopiedKubeConfig { source, copy } => vec![(
+                source.namespace.as_deref().unwrap_or_default(),
+                &source.secret,
+                &copy.secret,
+            )],
+            Self::TokenRequest { bootstrap, .. } => bootstrap.copies(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Individually stored credentials mounted into the forwarder
+    pub fn structured(&self) -> Option<&CredentialsReference> {
+        match self {
+            Self::Structured(reference) => Some(reference),
+            Self::TokenRequest { generated, .. } => Some(generated),
+            _ => None,
+        }
+    }
+
+    /// Every secret read by the controller or the forwarder
+    pub fn secret_names(&self) -> Vec<&str> {
+        match self {
+            Self::TokenRequest { bootstrap, .. } => {
+                let mut names = bootstrap.secret_names();
+                names.extend(self.secret());
+                names
+            }
+            _ => self.secret().into_iter().collect(),
+        }
+    }
+
+    /// Every config map read by the controller or the forwarder
+    pub fn config_map_names(&self) -> Vec<&str> {
+        match self {
+            Self::TokenRequest { bootstrap, .. } => bootstrap.config_map_names(),
+            _ => self.config_map().into_iter().collect(),
+        }
+    }
+}
+
+/// Prefix of the secrets generated for a `RemoteCluster`, distinct from those of a
+/// `ForwardedService` with the same name
+pub(crate) fn cluster_prefix(cluster: &RemoteCluster) -> String {
+    format!("{}-cluster", cluster.name_any())
+}
+
+/// Outcome of reading and checking the referenced credentials
+pub(crate) struct Inspection {
+    /// Content hash of the credentials, `None` when they do not exist yet
+    pub hash: Option<String>,
+    /// Expiry of the credentials, or the reason and message why they are unusable
+    pub result: Result<Option<DateTime<Utc>>, (&'static str, String)>,
+    /// Parsed kubeconfig, used when the controller itself talks to the remote cluster
+    pub kubeconfig: Option<Kubeconfig>,
+}
+
+impl Inspection {
+    pub fn invalid(hash: Option<String>, reason: &'static str, message: String) -> Self {
+        Self {
+            hash,
+            result: Err((reason, message)),
+            kubeconfig: None,
+        }
+    }
+
+    /// Client configuration for the remote cluster, when the credentials are usable
+    pub async fn config(&self, credentials: &Credentials<'_>) -> Result<Config, String> {
+        if let Err((_, message)) = &self.result {
+            return Err(message.clone());
+        }
+        if let Credentials::InCluster = credentials {
+            return Config::infer().await.map_err(|e| e.to_string());
+        }
+        let kubeconfig = self.kubeconfig.clone().ok_or("unreadable kubeconfig")?;
+        let options = match credentials.kube_config() {
+            Some(reference) => KubeConfigOptions {
+                context: Some(reference.context.clone()),
+                cluster: reference.cluster.clone(),
+                user: reference.user.clone(),
+            },
+            None => KubeConfigOptions::default(),
+        };
+        Config::from_custom_kubeconfig(kubeconfig, &options)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Client for the remote cluster, when the credentials are usable
+    pub async fn client(&self, credentials: &Credentials<'_>) -> Result<Client, String> {
+        Client::try_from(self.config(credentials).await?).map_err(|e| e.to_string())
+    }
+}
+
+/// Cached clients nobody asked for this long are dropped, well beyond the periodic
+/// reconcile of the resources using them
+const IDLE_CLIENT_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// A remote client, the credentials hash it was built from and when it was last used
+struct Cached {
+    hash: String,
+    client: Client,
+    used: Instant,
+}
+
+/// Remote clients by the secret and context they were built from, so reconciles reuse
+/// their connections. A client is rebuilt once the credentials hash changes, and
+/// dropped when its resource is deleted or it goes unused for `IDLE_CLIENT_TTL`.
+#[derive(Default)]
+pub(crate) struct Clients {
+    clients: Mutex<HashMap<String, Cached>>,
+}
+
+impl Clients {
+    /// Client for the remote cluster, when the credentials read from `namespace` are
+    /// usable. In-cluster credentials get `local`, the client of the controller.
+    pub async fn get(
+        &self,
+        local: &Client,
+        namespace: &str,
+        inspection: &Inspection,
+        credentials: &Credentials<'_>,
+    ) -> Result<Client, String> {
+        if let Err((_, message)) = &inspection.result {
+            return Err(message.clone());
+        }
+        if let Credentials::InCluster = credentials {
+            return Ok(local.clone());
+        }
+        let (Some(key), Some(hash)) = (Self::key(namespace, credentials), &inspection.hash) else {
+            return inspection.client(credentials).await;
+        };
+        if let Some(cached) = self.clients.lock().unwrap().get_mut(&key) {
+            if cached.hash == *hash {
+                cached.used = Instant::now();
+                return Ok(cached.client.clone());
+            }
+        }
+        let client = inspection.client(credentials).await?;
+        let mut clients = self.clients.lock().unwrap();
+        evict_idle(&mut clients, Instant::now());
+        clients.insert(
+            key,
+            Cached {
+                hash: hash.clone(),
+                client: client.clone(),
+                used: Instant::now(),
+            },
+        );
+        Ok(client)
+    }
+
+    /// Drops the client built from `credentials`, once the resource using them is deleted
+    pub fn forget(&self, namespace: &str, credentials: &Credentials<'_>) {
+        if let Some(key) = Self::key(namespace, credentials) {
+            self.clients.lock().unwrap().remove(&key);
+        }
+    }
+
+    fn key(namespace: &str, credentials: &Credentials<'_>) -> Option<String> {
+        let secret = credentials.secret()?;
+        Some(match credentials.kube_config() {
+            Some(reference) => format!(
+                "{namespace}/{secret}|{}|{}|{}",
+                reference.context,
+                reference.cluster.as_deref().unwrap_or_default(),
+                reference.user.as_deref().unwrap_or_default()
+            ),
+            None => format!("{namespace}/{secret}"),
+        })
+    }
+}
+
+fn evict_idle(clients: &mut HashMap<String, Cached>, now: Instant) {
+    clients.retain(|_, cached| now.duration_since(cached.used) < IDLE_CLIENT_TTL);
+}
+
+async fn secret_data(
+    client: Client,
+    namespace: &str,
+    name: &str,
+) -> Result<Option<BTreeMap<String, Vec<u8>>>, Error> {
+    let secrets: Api<Secret> = Api::namespaced(client, namespace);
+    let secret = secrets
+        .get_opt(name)
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+    Ok(secret.map(|s| {
+        s.data
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k, v.0))
+            .collect()
+    }))
+}
+
+async fn config_map_data(
+    client: Client,
+    namespace: &str,
+    name: &str,
+) -> Result<Option<BTreeMap<String, Vec<u8>>>, Error> {
+    let config_maps: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let config_map = config_maps
+        .get_opt(name)
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+    Ok(config_map.map(|c| {
+        let mut data: BTreeMap<String, Vec<u8>> = c
+            .binary_data
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k, v.0))
+            .collect();
+        data.extend(
+            c.data
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(k, v)| (k, v.into_bytes())),
+        );
+        data
+    }))
+}
+
+/// Reads the referenced credentials, hashes them and checks that they are usable
+pub(crate) async fn inspect(
+    client: Client,
+    namespace: &str,
+    credentials: &Credentials<'_>,
+) -> Result<Inspection, Error> {
+    let name = match credentials.secret() {
+        Some(name) => name,
+        None => {
+            return Ok(Inspection {
+                hash: None,
+                result: Ok(None),
+                kubeconfig: None,
+            })
+        }
+    };
+    let secret = match secret_data(client.clone(), namespace, name).await? {
+        Some(secret) => secret,
+        None => {
+            return Ok(Inspection::invalid(
+                None,
+                "NotFound",
+                format!("secret `{name}` not found"),
+            ))
+        }
+    };
+
+    match credentials {
+        Credentials::InCluster => unreachable!("in-cluster credentials have no secret"),
+        Credentials::KubeConfig(_) | Credentials::CopiedKubeConfig { .. } => {
+            let reference = credentials.kube_config().expect("kubeconfig credentials");
+            let contents = match secret.get(&reference.key_any()) {
+                Some(contents) => contents,
+                None => {
+                    return Ok(Inspection::invalid(
+                        None,
+                        "NotFound",
+                        format!(
+                            "secret `{}` has no key `{}`",
+                            reference.secret,
+                            reference.key_any()
+                        ),
+                    ))
+                }
+            };
+            Ok(Inspection {
+                hash: Some(hash(contents)),
+                result: inspect_kubeconfig(contents, reference).map_err(|m| ("Invalid", m)),
+                kubeconfig: std::str::from_utf8(contents)
+                    .ok()
+                    .and_then(|text| Kubeconfig::from_yaml(text).ok()),
+            })
+        }
+        Credentials::TokenRequest { generated, .. } => {
+            inspect_structured(client, namespace, generated, secret).await
+        }
+        Credentials::Structured(reference) => {
+            inspect_structured(client, namespace, reference, secret).await
+        }
+    }
+}
+
+async fn inspect_structured(
+    client: Client,
+    namespace: &str,
+    reference: &CredentialsReference,
+    secret: BTreeMap<String, Vec<u8>>,
+) -> Result<Inspection, Error> {
+    let config_map = match &reference.config_map {
+        Some(name) => match config_map_data(client, namespace, name).await? {
+            Some(data) => Some(data),
+            None => {
+                return Ok(Inspection::invalid(
+                    None,
+                    "NotFound",
+                    format!("config map `{name}` not found"),
+                ))
+            }
+        },
+        None => None,
+    };
+    let cluster = config_map.as_ref().unwrap_or(&secret);
+    let text = |data: &BTreeMap<String, Vec<u8>>, key: String| {
+        data.get(&key)
+            .map(|v| String::from_utf8_lossy(v).into_owned())
+    };
+    let assembled = ClusterCredentials {
+        server: text(cluster, reference.server_key_any()),
+        certificate_authority: text(cluster, reference.certificate_authority_key_any()),
+        token: text(&secret, reference.token_key_any()),
+        client_certificate: text(&secret, reference.client_certificate_key_any()),
+        client_key: text(&secret, reference.client_key_key_any()),
+    };
+    let kubeconfig = assembled.to_kubeconfig();
+    let hash = serde_yaml::to_string(&kubeconfig)
+        .ok()
+        .map(|yaml| hash(yaml.as_bytes()));
+    Ok(Inspection {
+        hash,
+        result: inspect_credentials(&assembled).map_err(|m| ("Invalid", m)),
+        kubeconfig: Some(kubeconfig),
+    })
+}
+
+const INLINE_ONLY: &str = "only inline token and certificate data is accepted";
+
+pub(crate) fn hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Checks that the context, user and cluster named by the reference exist and returns
+/// the earliest expiry of the embedded client certificate or bearer token
+pub(crate) fn inspect_kubeconfig(
+    contents: &[u8],
+    reference: &KubeConfigReference,
+) -> Result<Option<DateTime<Utc>>, String> {
+    let text = std::str::from_utf8(contents).map_err(|e| e.to_string())?;
+    let config = Kubeconfig::from_yaml(text).map_err(|e| e.to_string())?;
+    let context = config
+        .contexts
+        .iter()
+        .find(|c| c.name == reference.context)
+        .and_then(|c| c.context.as_ref())
+        .ok_or_else(|| format!("context `{}` not found", reference.context))?;
+
+    let user = reference.user.as_ref().unwrap_or(&context.user);
+    let auth_info = config
+        .auth_infos
+        .iter()
+        .find(|a| &a.name == user)
+        .ok_or_else(|| format!("user `{user}` not found"))?;
+
+    let cluster = reference.cluster.as_ref().unwrap_or(&context.cluster);
+    let cluster = config
+        .clusters
+        .iter()
+        .find(|c| &c.name == cluster)
+        .ok_or_else(|| format!("cluster `{cluster}` not found"))?;
+    if cluster
+        .cluster
+        .as_ref()
+        .is_some_and(|c| c.certificate_authority.is_some())
+    {
+        return Err(format!(
+            "cluster `{}` uses `certificate-authority`, {INLINE_ONLY}",
+            cluster.name
+        ));
+    }
+
+    let Some(auth_info_data) = &auth_info.auth_info else {
+        return Ok(None);
+    };
+    // the controller connects with these credentials, so it must neither run commands
+    // nor read files such as its own service account token
+    let outside = [
+        (auth_info_data.exec.is_some(), "exec"),
+        (auth_info_data.auth_provider.is_some(), "auth-provider"),
+        (auth_info_data.token_file.is_some(), "tokenFile"),
+        (
+            auth_info_data.client_certificate.is_some(),
+            "client-certificate",
+        ),
+        (auth_info_data.client_key.is_some(), "client-key"),
+    ];
+    if let Some((_, field)) = outside.iter().find(|(set, _)| *set) {
+        return Err(format!(
+            "user `{}` uses `{field}`, {INLINE_ONLY}",
+            auth_info.name
+        ));
+    }
+    let auth_info = auth_info_data;
+    let certificate = match &auth_info.client_certificate_data {
+        Some(data) => {
+            let pem = STANDARD
+                .decode(data)
+                .map_err(|e| format!("invalid client certificate: {e}"))?;
+            let pem = String::from_utf8_lossy(&pem);
+            Some(certificate_expiry(&pem).ok_or("invalid client certificate")?)
+        }
+        None => None,
+    };
+    let token = auth_info
+        .token
+        .as_ref()
+        .and_then(|t| token_expiry(t.expose_secret()));
+    Ok(earliest(certificate, token))
+}
+
+/// Checks individually stored credentials and returns their earliest expiry
+pub(crate) fn inspect_credentials(
+    credentials: &ClusterCredentials,
+) -> Result<Option<DateTime<Utc>>, String> {
+    credentials.validate()?;
+    let certificate = match &credentials.client_certificate {
+        Some(pem) => Some(certificate_expiry(pem).ok_or("invalid client certificate")?),
+        None => None,
+    };
+    let token = credentials.token.as_deref().and_then(token_expiry);
+    Ok(earliest(certificate, token))
+}
+
+pub(crate) fn earliest(
+    first: Option<DateTime<Utc>>,
+    second: Option<DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    match (first, second) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Reads the `exp` claim of a JWT bearer token, opaque tokens have no known expiry
+pub(crate) fn token_expiry(token: &str) -> Option<DateTime<Utc>> {
+    #[derive(serde::Deserialize)]
+    struct Claims {
+        exp: i64,
+    }
+
+    let payload = token.trim().split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims: Claims = serde_json::from_slice(&payload).ok()?;
+    Utc.timestamp_opt(claims.exp, 0).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Instant};
+
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use kube::Client;
+
+    use super::{
+        evict_idle, inspect_credentials, inspect_kubeconfig, token_expiry, Cached, Credentials,
+        IDLE_CLIENT_TTL,
+    };
+    use crate::{
+        crd::{
+            ForwardedService, ForwardedServiceSpec, KubeConfigReference, RemoteCluster,
+            RemoteClusterSpec,
+        },
+        kubeconfig::ClusterCredentials,
+    };
+
+    fn kubeconfig(token: &str) -> String {
+        format!(
+            r#"
+apiVersion: v1
+kind: Config
+clusters:
+- name: remote
+  cluster:
+    server: https://remote.example.com
+contexts:
+- name: remote
+  context:
+    cluster: remote
+    user: forwarder
+users:
+- name: forwarder
+  user:
+    token: {token}
+"#
+        )
+    }
+
+    fn jwt(exp: i64) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(format!(r#"{{"exp":{exp}}}"#));
+        format!("eyJhbGciOiJSUzI1NiJ9.{payload}.c2lnbmF0dXJl")
+    }
+
+    fn reference(context: &str) -> KubeConfigReference {
+        KubeConfigReference {
+            context: context.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_clients_are_evicted() {
+        let client = || {
+            let server = tower::service_fn(|_: hyper::Request<hyper::Body>| async {
+                Ok::<_, std::convert::Infallible>(hyper::Response::new(hyper::Body::empty()))
+            });
+            Client::new(server, "default")
+        };
+        let now = Instant::now();
+        let cached = |used| Cached {
+            hash: "hash".to_owned(),
+            client: client(),
+            used,
+        };
+        let mut clients = HashMap::from([
+            ("default/fresh".to_owned(), cached(now)),
+            ("default/idle".to_owned(), cached(now)),
+        ]);
+        clients.get_mut("default/fresh").unwrap().used = now + IDLE_CLIENT_TTL;
+        evict_idle(&mut clients, now + IDLE_CLIENT_TTL);
+        assert_eq!(vec!["default/fresh"], clients.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_token_expiry_from_jwt() {
+        let expiry = token_expiry(&jwt(1_700_000_000)).unwrap();
+        assert_eq!(1_700_000_000, expiry.timestamp());
+    }
+
+    #[test]
+    fn test_token_expiry_opaque_token() {
+        assert!(token_expiry("abcdef").is_none());
+    }
+
+    #[test]
+    fn test_inspect_valid_kubeconfig() {
+        let config = kubeconfig(&jwt(1_700_000_000));
+        let expiry = inspect_kubeconfig(config.as_bytes(), &reference("remote")).unwrap();
+        assert_eq!(Some(1_700_000_000), expiry.map(|e| e.timestamp()));
+    }
+
+    #[test]
+    fn test_inspect_missing_context() {
+        let config = kubeconfig("abcdef");
+        let error = inspect_kubeconfig(config.as_bytes(), &reference("missing")).unwrap_err();
+        assert_eq!("context `missing` not found", error);
+    }
+
+    #[test]
+    fn test_inspect_refuses_commands_and_files() {
+        let config = kubeconfig("abcdef");
+        for (field, user) in [
+            (
+                "exec",
+                "exec:\n      apiVersion: client.authentication.k8s.io/v1\n      command: sh",
+            ),
+            ("auth-provider", "auth-provider:\n      name: gcp"),
+            (
+                "tokenFile",
+                "tokenFile: /var/run/secrets/kubernetes.io/serviceaccount/token",
+            ),
+            ("client-certificate", "client-certificate: /etc/tls.crt"),
+            ("client-key", "client-key: /etc/tls.key"),
+        ] {
+            let config = config.replace("token: abcdef", user);
+            assert_eq!(
+                format!("user `forwarder` uses `{field}`, only inline token and certificate data is accepted"),
+                inspect_kubeconfig(config.as_bytes(), &reference("remote")).unwrap_err()
+            );
+        }
+
+        let config = config.replace(
+            "server: https://remote.example.com",
+            "server: https://remote.example.com\n    certificate-authority: /etc/ca.crt",
+        );
+        assert!(inspect_kubeconfig(config.as_bytes(), &reference("remote"))
+            .unwrap_err()
+            .contains("certificate-authority"));
+    }
+
+    #[test]
+    fn test_inspect_credentials_token_expiry() {
+        let credentials = ClusterCredentials {
+            server: Some("https://remote.example.com".to_owned()),
+            token: Some(jwt(1_700_000_000)),
+            ..Default::default()
+        };
+        let expiry = inspect_credentials(&credentials).unwrap();
+        assert_eq!(Some(1_700_000_000), expiry.map(|e| e.timestamp()));
+    }
+
+    #[test]
+    fn test_inspect_credentials_without_token() {
+        let credentials = ClusterCredentials {
+            server: Some("https://remote.example.com".to_owned()),
+            ..Default::default()
+        };
+        assert!(inspect_credentials(&credentials).is_err());
+    }
+
+    #[test]
+    fn test_inspect_missing_user_and_cluster() {
+        let config = kubeconfig("abcdef");
+        let mut with_user = reference("remote");
+        with_user.user = Some("other".to_owned());
+        assert_eq!(
+            "user `other` not found",
+            inspect_kubeconfig(config.as_bytes(), &with_user).unwrap_err()
+        );
+
+        let mut with_cluster = reference("remote");
+        with_cluster.cluster = Some("other".to_owned());
+        assert_eq!(
+            "cluster `other` not found",
+            inspect_kubeconfig(config.as_bytes(), &with_cluster).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_from_spec_with_cluster_ref() {
+        let fs = ForwardedService::new(
+            "db",
+            ForwardedServiceSpec {
+                cluster_ref: Some("staging".to_owned()),
+                ..Default::default()
+            },
+        );
+        let mut cluster_spec = RemoteClusterSpec::default();
+        cluster_spec.connection.kube_config = Some(reference("remote"));
+        let cluster = RemoteCluster::new("staging", cluster_spec);
+
+        assert!(matches!(
+            Credentials::from_spec(&fs, Some(&cluster)),
+            Ok(Credentials::KubeConfig(_))
+        ));
+        assert_eq!(
+            "remote cluster `staging` not found",
+            Credentials::from_spec(&fs, None).err().unwrap()
+        );
+    }
+}
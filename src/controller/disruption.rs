@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::{
+    api::{
+        core::v1::{
+            Affinity, PodAffinityTerm, PodAntiAffinity, PodTemplateSpec, TopologySpreadConstraint,
+            WeightedPodAffinityTerm,
+        },
+        policy::v1::{PodDisruptionBudget, PodDisruptionBudgetSpec},
+    },
+    apimachinery::pkg::{apis::meta::v1::LabelSelector, util::intstr::IntOrString},
+};
+use kube::{
+    api::{Patch, PatchParams},
+    core::ObjectMeta,
+    Api, Client, ResourceExt,
+};
+
+use super::{delete_if_exists, owner_reference};
+use crate::{crd::ForwardedService, error::Error};
+
+const LABEL_FORWARDED_SERVICE: &str = "port-forward-operator.rs/forwardedservice";
+const TOPOLOGY_KEYS: [&str; 2] = ["kubernetes.io/hostname", "topology.kubernetes.io/zone"];
+
+fn selector(fs: &ForwardedService) -> LabelSelector {
+    LabelSelector {
+        match_labels: Some(BTreeMap::from([(
+            LABEL_FORWARDED_SERVICE.to_owned(),
+            fs.name_any(),
+        )])),
+        ..Default::default()
+    }
+}
+
+/// Spreads the forwarder pods over nodes and zones as far as the cluster allows, so a
+/// single node or zone going down leaves the others forwarding
+pub(crate) fn spread(fs: &ForwardedService, pod: &mut PodTemplateSpec) {
+    let Some(spec) = pod.spec.as_mut() else {
+        return;
+    };
+    spec.topology_spread_constraints = Some(
+        TOPOLOGY_KEYS
+            .iter()
+            .map(|key| TopologySpreadConstraint {
+                label_selector: Some(selector(fs)),
+                max_skew: 1,
+                topology_key: key.to_string(),
+                when_unsatisfiable: "ScheduleAnyway".to_owned(),
+            })
+            .collect(),
+    );
+    spec.affinity = Some(Affinity {
+        pod_anti_affinity: Some(PodAntiAffinity {
+            preferred_during_scheduling_ignored_during_execution: Some(
+                TOPOLOGY_KEYS
+                    .iter()
+                    .map(|key| WeightedPodAffinityTerm {
+                        pod_affinity_term: PodAffinityTerm {
+                            label_selector: Some(selector(fs)),
+                            topology_key: key.to_string(),
+                            ..Default::default()
+                        },
+                        weight: 100,
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+}
+
+/// Keeps a disruption budget evicting one forwarder pod at a time while the dedicated
+/// forwarder runs several, removing it otherwise
+pub(crate) async fn sync(
+    client: Client,
+    fs: &ForwardedService,
+    replicas: i32,
+) -> Result<(), Error> {
+    let ns = fs.namespace().unwrap();
+    let budgets: Api<PodDisruptionBudget> = Api::namespaced(client, &ns);
+    if replicas < 2 {
+        return delete_if_exists(budgets, &fs.name_any()).await;
+    }
+    let budget = PodDisruptionBudget {
+        metadata: ObjectMeta {
+            name: Some(fs.name_any()),
+            namespace: Some(ns),
+            labels: Some(BTreeMap::from([(
+                LABEL_FORWARDED_SERVICE.to_owned(),
+                fs.name_any(),
+            )])),
+            owner_references: Some(vec![owner_reference(fs)]),
+            ..Default::default()
+        },
+        spec: Some(PodDisruptionBudgetSpec {
+            max_unavailable: Some(IntOrString::Int(1)),
+            selector: Some(selector(fs)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    budgets
+        .patch(
+            &fs.name_any(),
+            &PatchParams::apply("port-forward-operator").force(),
+            &Patch::Apply(&budget),
+        )
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::{PodSpec, PodTemplateSpec};
+
+    use super::spread;
+    use crate::crd::{ForwardedService, ForwardedServiceSpec};
+
+    #[test]
+    fn test_spread_over_nodes_and_zones() {
+        let fs = ForwardedService::new("postgres", ForwardedServiceSpec::default());
+        let mut pod = PodTemplateSpec {
+            spec: Some(PodSpec::default()),
+            ..Default::default()
+        };
+        spread(&fs, &mut pod);
+        let spec = pod.spec.unwrap();
+        let keys: Vec<String> = spec
+            .topology_spread_constraints
+            .unwrap()
+            .into_iter()
+            .map(|c| c.topology_key)
+            .collect();
+        assert_eq!(
+            vec!["kubernetes.io/hostname", "topology.kubernetes.io/zone"],
+            keys
+        );
+        assert!(spec.affinity.unwrap().pod_anti_affinity.is_some());
+    }
+}
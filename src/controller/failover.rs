@@ -0,0 +1,143 @@
+use k8s_openapi::api::core::v1::{Pod, Volume, VolumeMount};
+use kube::{
+    api::{GetParams, ListParams},
+    core::Request,
+    Api, Client, ResourceExt,
+};
+
+use super::{
+    credential_paths,
+    credentials::{cluster_prefix, Credentials},
+    mount_credentials,
+};
+use crate::{
+    crd::{ForwardedService, RemoteCluster},
+    error::Error,
+    service::{is_ready, FailoverCluster, FailoverConfig, FailoverStatus},
+};
+
+const CLUSTERS_PATH: &str = "/etc/port-forward-operator/clusters";
+const LABEL_FORWARDED_SERVICE: &str = "port-forward-operator.rs/forwardedservice";
+
+/// Name the forwarder uses for the cluster of the spec
+pub(crate) fn primary_name(fs: &ForwardedService) -> String {
+    fs.spec
+        .cluster_ref
+        .clone()
+        .unwrap_or_else(|| "primary".to_owned())
+}
+
+/// The referenced clusters that exist, and the names of those that do not
+pub(crate) async fn clusters<'a>(
+    client: Client,
+    fs: &ForwardedService,
+    names: impl IntoIterator<Item = &'a String>,
+) -> Result<(Vec<RemoteCluster>, Vec<String>), Error> {
+    let api = Api::<RemoteCluster>::namespaced(client, &fs.namespace().unwrap());
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for name in names {
+        match api
+            .get_opt(name)
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?
+        {
+            Some(cluster) => found.push(cluster),
+            None => missing.push(name.clone()),
+        }
+    }
+    Ok((found, missing))
+}
+
+/// Credentials of the other clusters a forwarder connects to, with their mounts
+pub(crate) fn mount_clusters(
+    clusters: &[RemoteCluster],
+) -> (Vec<FailoverCluster>, Vec<VolumeMount>, Vec<Volume>) {
+    let mut mounted = Vec::new();
+    let mut volume_mounts = Vec::new();
+    let mut volumes = Vec::new();
+    for (i, cluster) in clusters.iter().enumerate() {
+        let credentials = match Credentials::from_connection(
+            &cluster.spec.connection,
+            &cluster_prefix(cluster),
+        ) {
+            Ok(credentials) => credentials,
+            Err(message) => {
+                tracing::warn!("skipping cluster {}: {}", cluster.name_any(), message);
+                continue;
+            }
+        };
+        let kube_config_path = format!("{CLUSTERS_PATH}/{i}/kube");
+        let cluster_config_path = format!("{CLUSTERS_PATH}/{i}/cluster");
+        mounted.push(FailoverCluster {
+            name: cluster.name_any(),
+            credentials: credential_paths(&credentials, &kube_config_path, &cluster_config_path),
+        });
+        let (mounts, added) = mount_credentials(
+            credentials.secret(),
+            credentials.config_map(),
+            &format!("cluster-{i}-"),
+            &kube_config_path,
+            &cluster_config_path,
+        );
+        volume_mounts.extend(mounts);
+        volumes.extend(added);
+    }
+    (mounted, volume_mounts, volumes)
+}
+
+/// Argument passing the failover config to the forwarder, and the mounts of the
+/// credentials of every fallback cluster
+pub(crate) fn forwarder(
+    fs: &ForwardedService,
+    clusters: &[RemoteCluster],
+) -> (Vec<String>, Vec<VolumeMount>, Vec<Volume>) {
+    let failover = fs.spec.failover.clone().unwrap_or_default();
+    let (clusters, volume_mounts, volumes) = mount_clusters(clusters);
+    let config = FailoverConfig {
+        primary: primary_name(fs),
+        clusters,
+        failure_threshold: failover.failure_threshold.unwrap_or(3),
+        success_threshold: failover.success_threshold.unwrap_or(5),
+        period_seconds: u64::from(failover.period_seconds.unwrap_or(10)),
+    };
+    let args = vec![
+        "--failover".to_owned(),
+        serde_json::to_string(&config).expect("serializable failover config"),
+    ];
+    (args, volume_mounts, volumes)
+}
+
+/// Active cluster and failover count reported by a ready forwarder pod, through the
+/// API server proxy
+pub(crate) async fn status(client: Client, fs: &ForwardedService) -> Option<FailoverStatus> {
+    let ns = fs.namespace()?;
+    let pods = Api::<Pod>::namespaced(client.clone(), &ns)
+        .list(
+            &ListParams::default().labels(&format!("{LABEL_FORWARDED_SERVICE}={}", fs.name_any())),
+        )
+        .await
+        .ok()?;
+    let pod = pods.iter().find(|pod| is_ready(pod))?;
+    // the metrics port moves when the forward uses the default one
+    let port = pod
+        .spec
+        .as_ref()?
+        .containers
+        .iter()
+        .flat_map(|container| container.ports.iter().flatten())
+        .find(|port| port.name.as_deref() == Some("metrics"))?
+        .container_port;
+    let request = Request::new(format!("/api/v1/namespaces/{ns}/pods"))
+        .get(
+            &format!("{}:{port}/proxy/failover", pod.name_any()),
+            &GetParams::default(),
+        )
+        .ok()?;
+    let text = client
+        .request_text(request)
+        .await
+        .map_err(|e| tracing::debug!("unable to read failover status: {}", e))
+        .ok()?;
+    serde_json::from_str(&text).ok()
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    core::ObjectMeta,
+    Api, Client, Resource, ResourceExt,
+};
+
+use super::{credentials::Credentials, delete_if_exists, owner_reference};
+use crate::{crd::SecretReferenceGrant, error::Error};
+
+const ANNOTATION_COPIED_FROM: &str = "port-forward-operator.rs/copied-from";
+/// Label of every copied secret, to find the copies an owner no longer references
+const LABEL_COPY: &str = "port-forward-operator.rs/copy";
+
+/// Whether `grant` allows objects in namespace `from` to reference `secret`
+pub(crate) fn permits(grant: &SecretReferenceGrant, from: &str, secret: &str) -> bool {
+    grant.spec.from.iter().any(|f| f.namespace == from)
+        && grant
+            .spec
+            .to
+            .iter()
+            .any(|t| t.name.as_deref().is_none_or(|name| name == secret))
+}
+
+/// Whether the credentials reference a secret in namespace `ns` through a copy
+pub(crate) fn references_namespace(credentials: &Credentials, ns: &str) -> bool {
+    credentials
+        .copies()
+        .iter()
+        .any(|(source_ns, _, _)| *source_ns == ns)
+}
+
+/// Namespace and name of the secrets the credentials copy from other namespaces
+pub(crate) fn sources<'a>(credentials: &'a Credentials) -> Vec<(&'a str, &'a str)> {
+    credentials
+        .copies()
+        .into_iter()
+        .map(|(source_ns, source, _)| (source_ns, source))
+        .collect()
+}
+
+/// Copies the kubeconfig secrets referenced from other namespaces next to `owner`. A copy
+/// is removed again once no grant allows it or `owner` references another secret.
+/// Returns the reason and message when a reference is not permitted or its secret does
+/// not exist.
+pub(crate) async fn sync<K>(
+    client: Client,
+    owner: &K,
+    credentials: &Credentials<'_>,
+) -> Result<Result<(), (&'static str, String)>, Error>
+where
+    K: Resource<DynamicType = ()>,
+{
+    let ns = owner.namespace().unwrap();
+    let local: Api<Secret> = Api::namespaced(client.clone(), &ns);
+    let copies = credentials.copies();
+    let keep = copies.iter().map(|(_, _, copy)| *copy).collect::<Vec<_>>();
+    prune(client.clone(), owner, &keep).await?;
+    for (source_ns, name, copy) in copies {
+        let permitted = source_ns == ns
+            || Api::<SecretReferenceGrant>::namespaced(client.clone(), source_ns)
+                .list(&ListParams::default())
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?
+                .iter()
+                .any(|grant| permits(grant, &ns, name));
+        if !permitted {
+            delete_if_exists(local.clone(), copy).await?;
+            return Ok(Err((
+                "ReferenceNotPermitted",
+                format!(
+                    "no SecretReferenceGrant in namespace `{source_ns}` allows namespace `{ns}` to reference secret `{name}`"
+                ),
+            )));
+        }
+
+        let source = match Api::<Secret>::namespaced(client.clone(), source_ns)
+            .get_opt(name)
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?
+        {
+            Some(source) => source,
+            None => {
+                return Ok(Err((
+                    "NotFound",
+                    format!("secret `{source_ns}/{name}` not found"),
+                )))
+            }
+        };
+        let secret = Secret {
+            metadata: ObjectMeta {
+                name: Some(copy.to_owned()),
+                namespace: Some(ns.clone()),
+                labels: Some(BTreeMap::from([(LABEL_COPY.to_owned(), "true".to_owned())])),
+                annotations: Some(BTreeMap::from([(
+                    ANNOTATION_COPIED_FROM.to_owned(),
+                    format!("{source_ns}/{name}"),
+                )])),
+                owner_references: Some(vec![owner_reference(owner)]),
+                ..Default::default()
+            },
+            data: source.data,
+            type_: source.type_,
+            ..Default::default()
+        };
+        local
+            .patch(
+                copy,
+                &PatchParams::apply("port-forward-operator").force(),
+                &Patch::Apply(&secret),
+            )
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?;
+    }
+    Ok(Ok(()))
+}
+
+/// Removes the copies made for `owner` other than `keep`
+pub(crate) async fn prune<K>(client: Client, owner: &K, keep: &[&str]) -> Result<(), Error>
+where
+    K: Resource<DynamicType = ()>,
+{
+    let local: Api<Secret> = Api::namespaced(client, &owner.namespace().unwrap());
+    for copy in local
+        .list_metadata(&ListParams::default().labels(&format!("{LABEL_COPY}=true")))
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?
+    {
+        let owned = copy
+            .owner_references()
+            .iter()
+            .any(|o| Some(&o.uid) == owner.meta().uid.as_ref());
+        if owned && !keep.contains(&copy.name_any().as_str()) {
+            delete_if_exists(local.clone(), &copy.name_any()).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::permits;
+    use crate::crd::{
+        SecretReferenceGrant, SecretReferenceGrantFrom, SecretReferenceGrantSpec,
+        SecretReferenceGrantTo,
+    };
+
+    fn grant(name: Option<&str>) -> SecretReferenceGrant {
+        SecretReferenceGrant::new(
+            "clusters",
+            SecretReferenceGrantSpec {
+                from: vec![SecretReferenceGrantFrom {
+                    namespace: "team-a".to_owned(),
+                }],
+                to: vec![SecretReferenceGrantTo {
+                    name: name.map(str::to_owned),
+                }],
+            },
+        )
+    }
+
+    #[test]
+    fn test_permits_named_secret() {
+        assert!(permits(&grant(Some("staging")), "team-a", "staging"));
+        assert!(!permits(&grant(Some("staging")), "team-a", "production"));
+        assert!(!permits(&grant(Some("staging")), "team-b", "staging"));
+    }
+
+    #[test]
+    fn test_permits_every_secret_without_name() {
+        assert!(permits(&grant(None), "team-a", "production"));
+    }
+}
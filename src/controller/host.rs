@@ -1,10 +1,15 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
 use axum::{routing::get, Router};
 use axum_prometheus::PrometheusMetricLayerBuilder;
+use axum_server::tls_rustls::RustlsConfig;
+use kube::Client;
 
+use super::tls::{self, TlsBundle, TlsOptions};
 use crate::error::Error;
 
+const CERTIFICATE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 pub async fn start_host(address: &str) -> Result<(), Error> {
     let app = create_router();
     let addr: SocketAddr = match address.parse() {
@@ -17,6 +22,69 @@ pub async fn start_host(address: &str) -> Result<(), Error> {
         .map_err(|e| Error::Server(e.to_string()))
 }
 
+pub async fn start_webhook_host(address: &str, options: TlsOptions) -> Result<(), Error> {
+    let addr: SocketAddr = match address.parse() {
+        Ok(addr) => addr,
+        Err(e) => return Err(Error::Server(e.to_string())),
+    };
+    let client = Client::try_default()
+        .await
+        .map_err(|e| Error::KubeClient { source: e })?;
+    let bundle = tls::ensure_bundle(&client, &options).await?;
+    let config = RustlsConfig::from_pem(bundle.cert.clone().into(), bundle.key.clone().into())
+        .await
+        .map_err(|e| Error::Tls(e.to_string()))?;
+    if let Err(e) = tls::inject_ca_bundle(&client, &bundle.ca_cert).await {
+        tracing::warn!("unable to inject ca bundle: {}", e);
+    }
+    tokio::spawn(rotate_certificates(client, options, bundle, config.clone()));
+
+    axum_server::bind_rustls(addr, config)
+        .serve(create_webhook_router().into_make_service())
+        .await
+        .map_err(|e| Error::Server(e.to_string()))
+}
+
+async fn rotate_certificates(
+    client: Client,
+    options: TlsOptions,
+    mut current: TlsBundle,
+    config: RustlsConfig,
+) {
+    loop {
+        tokio::time::sleep(CERTIFICATE_CHECK_INTERVAL).await;
+        let bundle = match tls::ensure_bundle(&client, &options).await {
+            Ok(bundle) => bundle,
+            Err(e) => {
+                tracing::warn!("unable to refresh webhook certificate: {}", e);
+                continue;
+            }
+        };
+        if bundle == current {
+            continue;
+        }
+
+        if let Err(e) = config
+            .reload_from_pem(bundle.cert.clone().into(), bundle.key.clone().into())
+            .await
+        {
+            tracing::warn!("unable to reload webhook certificate: {}", e);
+            continue;
+        }
+        tracing::info!("reloaded webhook certificate");
+        if bundle.ca_cert != current.ca_cert {
+            if let Err(e) = tls::inject_ca_bundle(&client, &bundle.ca_cert).await {
+                tracing::warn!("unable to inject ca bundle: {}", e);
+            }
+        }
+        current = bundle;
+    }
+}
+
+fn create_webhook_router() -> Router {
+    Router::new().route("/health", get(|| async move {}))
+}
+
 fn create_router() -> Router {
     let (prometheus_layer, metric_handle) = PrometheusMetricLayerBuilder::new()
         .with_ignore_patterns(&["/metrics", "/sensitive", "/health"])
@@ -0,0 +1,224 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::RwLock,
+};
+
+use kube::{
+    runtime::reflector::{ObjectRef, Store},
+    Resource, ResourceExt,
+};
+
+use super::{credentials::Credentials, grant};
+
+/// Kind of an object credentials are read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum Kind {
+    Secret,
+    ConfigMap,
+}
+
+/// A secret or config map by kind, namespace and name
+pub(crate) type Referenced = (Kind, String, String);
+
+/// The secrets and config maps credentials in namespace `ns` read, including the
+/// sources of copies from other namespaces
+pub(crate) fn referenced(credentials: &Credentials, ns: &str) -> BTreeSet<Referenced> {
+    let secrets = credentials
+        .secret_names()
+        .into_iter()
+        .map(|name| (Kind::Secret, ns.to_owned(), name.to_owned()));
+    let config_maps = credentials
+        .config_map_names()
+        .into_iter()
+        .map(|name| (Kind::ConfigMap, ns.to_owned(), name.to_owned()));
+    let copied = grant::sources(credentials)
+        .into_iter()
+        .map(|(source_ns, name)| (Kind::Secret, source_ns.to_owned(), name.to_owned()));
+    secrets.chain(config_maps).chain(copied).collect()
+}
+
+/// Reverse index from the secrets and config maps to the objects whose credentials read
+/// them, so a change is mapped to its dependents without caching the secrets themselves
+/// or scanning every object. Entries are recorded on reconcile.
+pub(crate) struct References<K: Resource<DynamicType = ()>> {
+    index: RwLock<Index<K>>,
+}
+
+struct Index<K: Resource<DynamicType = ()>> {
+    dependents: HashMap<Referenced, HashSet<ObjectRef<K>>>,
+    referenced: HashMap<ObjectRef<K>, BTreeSet<Referenced>>,
+}
+
+impl<K: Resource<DynamicType = ()>> Default for References<K> {
+    fn default() -> Self {
+        Self {
+            index: RwLock::new(Index {
+                dependents: HashMap::new(),
+                referenced: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl<K: Resource<DynamicType = ()> + Clone + 'static> References<K> {
+    /// Replaces what `object` references
+    pub fn set(&self, object: &K, referenced: BTreeSet<Referenced>) {
+        let owner = ObjectRef::from_obj(object);
+        let mut index = self.index.write().unwrap();
+        if index.referenced.get(&owner) == Some(&referenced) {
+            return;
+        }
+        index.unlink(&owner);
+        for key in &referenced {
+            index
+                .dependents
+                .entry(key.clone())
+                .or_default()
+                .insert(owner.clone());
+        }
+        index.referenced.insert(owner, referenced);
+    }
+
+    pub fn remove(&self, object: &K) {
+        let owner = ObjectRef::from_obj(object);
+        self.index.write().unwrap().unlink(&owner);
+    }
+
+    /// Objects reading the changed secret or config map that still exist
+    pub fn dependents<T: ResourceExt>(
+        &self,
+        kind: Kind,
+        changed: &T,
+        store: &Store<K>,
+    ) -> Vec<ObjectRef<K>> {
+        let key = (
+            kind,
+            changed.namespace().unwrap_or_default(),
+            changed.name_any(),
+        );
+        let (existing, gone): (Vec<_>, Vec<_>) = self
+            .index
+            .read()
+            .unwrap()
+            .dependents
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .partition(|owner| store.get(owner).is_some());
+        if !gone.is_empty() {
+            let mut index = self.index.write().unwrap();
+            for owner in &gone {
+                index.unlink(owner);
+            }
+        }
+        existing
+    }
+}
+
+impl<K: Resource<DynamicType = ()>> Index<K> {
+    fn unlink(&mut self, owner: &ObjectRef<K>) {
+        for key in self.referenced.remove(owner).into_iter().flatten() {
+            if let Some(owners) = self.dependents.get_mut(&key) {
+                owners.remove(owner);
+                if owners.is_empty() {
+                    self.dependents.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+    use kube::{
+        core::ObjectMeta,
+        runtime::{
+            reflector::{self, ObjectRef},
+            watcher,
+        },
+    };
+
+    use super::{referenced, Kind, References};
+    use crate::{
+        controller::credentials::Credentials,
+        crd::{ClusterConnection, ForwardedService, ForwardedServiceSpec, KubeConfigReference},
+    };
+
+    fn forwarded_service(
+        ns: &str,
+        name: &str,
+        secret: &str,
+        secret_ns: Option<&str>,
+    ) -> ForwardedService {
+        let mut fs = ForwardedService::new(
+            name,
+            ForwardedServiceSpec {
+                service: "postgres".to_owned(),
+                connection: ClusterConnection {
+                    kube_config: Some(KubeConfigReference {
+                        secret: secret.to_owned(),
+                        namespace: secret_ns.map(str::to_owned),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        fs.metadata.namespace = Some(ns.to_owned());
+        fs
+    }
+
+    fn object<K: kube::Resource + Default>(ns: &str, name: &str) -> K {
+        let mut object = K::default();
+        *object.meta_mut() = ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(ns.to_owned()),
+            ..Default::default()
+        };
+        object
+    }
+
+    #[test]
+    fn test_rotated_secret_maps_to_referencing_services() {
+        let services = [
+            forwarded_service("team", "a", "remote", None),
+            forwarded_service("team", "b", "other", None),
+            forwarded_service("dev", "c", "remote", Some("team")),
+            forwarded_service("dev", "d", "remote", None),
+        ];
+        let (store, mut writer) = reflector::store();
+        let references = References::default();
+        for fs in &services {
+            writer.apply_watcher_event(&watcher::Event::Applied(fs.clone()));
+            let credentials = Credentials::from_spec(fs, None).unwrap();
+            references.set(
+                fs,
+                referenced(&credentials, &fs.metadata.namespace.clone().unwrap()),
+            );
+        }
+
+        let rotated: Secret = object("team", "remote");
+        let mut dependents: Vec<String> = references
+            .dependents(Kind::Secret, &rotated, &store)
+            .into_iter()
+            .map(|fs| fs.name)
+            .collect();
+        dependents.sort();
+        assert_eq!(vec!["a", "c"], dependents);
+
+        let same_name: ConfigMap = object("team", "remote");
+        assert!(references
+            .dependents(Kind::ConfigMap, &same_name, &store)
+            .is_empty());
+
+        // a deleted service is dropped from the index
+        writer.apply_watcher_event(&watcher::Event::Deleted(services[0].clone()));
+        assert_eq!(
+            vec![ObjectRef::from_obj(&services[2])],
+            references.dependents(Kind::Secret, &rotated, &store)
+        );
+    }
+}
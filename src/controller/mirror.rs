@@ -0,0 +1,436 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use futures::{channel::mpsc, StreamExt};
+use k8s_openapi::{api::core::v1::Service, apimachinery::pkg::apis::meta::v1::OwnerReference};
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    core::ObjectMeta,
+    runtime::{
+        controller::Action,
+        finalizer::{finalizer, Event as Finalizer},
+        reflector::ObjectRef,
+        watcher::{self, watcher},
+        Controller, WatchStreamExt,
+    },
+    Api, Client, ResourceExt,
+};
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use super::{
+    cluster, credentials, delete_if_exists, error_policy, owner_reference, rbac, status, Context,
+};
+use crate::{
+    crd::{
+        ForwardedService, ForwardedServiceSpec, RemoteCluster, ServiceMirror, ServiceMirrorStatus,
+    },
+    error::Error,
+};
+
+const SERVICE_MIRROR_FINALIZER: &str = "servicemirrors.port-forward-operator.rs";
+const LABEL_MIRROR: &str = "port-forward-operator.rs/servicemirror";
+/// Limit of a service name
+const MAX_NAME_LENGTH: usize = 63;
+
+/// A watch on remote services, with the cluster, namespace and selector it was started for
+type RemoteWatch = (String, JoinHandle<()>);
+
+/// State of the mirror controller. Each mirror has a watch on its remote services that
+/// triggers a reconcile whenever one of them changes.
+struct Mirrors {
+    ctx: Arc<Context>,
+    trigger: mpsc::UnboundedSender<ObjectRef<ServiceMirror>>,
+    watches: Mutex<HashMap<ObjectRef<ServiceMirror>, RemoteWatch>>,
+}
+
+impl Mirrors {
+    /// Starts watching the remote services of `mirror`, unless an equivalent watch runs
+    async fn watch(
+        &self,
+        mirror: &ServiceMirror,
+        remote: Api<Service>,
+        key: String,
+        selector: &str,
+    ) {
+        let obj = ObjectRef::from_obj(mirror);
+        let mut watches = self.watches.lock().await;
+        if let Some((running, handle)) = watches.get(&obj) {
+            if *running == key && !handle.is_finished() {
+                return;
+            }
+            handle.abort();
+        }
+        let mut config = watcher::Config::default();
+        if !selector.is_empty() {
+            config = config.labels(selector);
+        }
+        let trigger = self.trigger.clone();
+        let mirrored = obj.clone();
+        let handle = tokio::spawn(async move {
+            watcher(remote, config)
+                .default_backoff()
+                .touched_objects()
+                .for_each(|service| {
+                    if service.is_ok() {
+                        let _ = trigger.unbounded_send(mirrored.clone());
+                    }
+                    futures::future::ready(())
+                })
+                .await
+        });
+        watches.insert(obj, (key, handle));
+    }
+
+    async fn stop(&self, mirror: &ServiceMirror) {
+        if let Some((_, handle)) = self
+            .watches
+            .lock()
+            .await
+            .remove(&ObjectRef::from_obj(mirror))
+        {
+            handle.abort();
+        }
+    }
+}
+
+/// Reconciles `ServiceMirror`s into one `ForwardedService` per matching remote service
+pub(crate) async fn run(client: Client, ctx: Arc<Context>) {
+    let (trigger, triggered) = mpsc::unbounded();
+    let mirrors = Arc::new(Mirrors {
+        ctx,
+        trigger,
+        watches: Mutex::default(),
+    });
+    let api = Api::<ServiceMirror>::all(client.clone());
+    let controller = Controller::new(api, watcher::Config::default().any_semantic());
+    let cluster_store = controller.store();
+    controller
+        .owns(
+            Api::<ForwardedService>::all(client.clone()),
+            watcher::Config::default().labels(LABEL_MIRROR),
+        )
+        .watches(
+            Api::<RemoteCluster>::all(client),
+            watcher::Config::default(),
+            move |cluster| {
+                cluster_store
+                    .state()
+                    .into_iter()
+                    .filter(|mirror| {
+                        mirror.namespace() == cluster.namespace()
+                            && mirror.spec.cluster_ref.as_deref()
+                                == Some(cluster.name_any().as_str())
+                    })
+                    .map(|mirror| ObjectRef::from_obj(mirror.as_ref()))
+                    .collect::<Vec<_>>()
+            },
+        )
+        .reconcile_on(triggered.map(Ok))
+        .shutdown_on_signal()
+        .run(reconcile, error_policy, mirrors)
+        .filter_map(|x| async move { std::result::Result::ok(x) })
+        .for_each(|_| futures::future::ready(()))
+        .await;
+}
+
+/// Name of the `ForwardedService` mirroring `service`. It also names the local service,
+/// so a longer name is cut and kept unique by a hash of the full one.
+fn child_name(mirror: &ServiceMirror, service: &str) -> String {
+    let name = format!("{}-{}", mirror.name_any(), service);
+    if name.len() <= MAX_NAME_LENGTH {
+        return name;
+    }
+    let hash = &credentials::hash(name.as_bytes())[..10];
+    let prefix = name[..MAX_NAME_LENGTH - hash.len() - 1].trim_end_matches('-');
+    format!("{prefix}-{hash}")
+}
+
+/// Ports of a remote service the forwarder can carry
+fn mirrored_ports(service: &Service) -> Vec<String> {
+    service
+        .spec
+        .iter()
+        .flat_map(|spec| spec.ports.iter().flatten())
+        .filter(|port| port.protocol.as_deref().unwrap_or("TCP") == "TCP")
+        .map(|port| port.port.to_string())
+        .collect()
+}
+
+fn selector(mirror: &ServiceMirror) -> String {
+    mirror
+        .spec
+        .selector
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+async fn reconcile(mirror: Arc<ServiceMirror>, mirrors: Arc<Mirrors>) -> Result<Action, Error> {
+    mirrors.ctx.diagnostics.write().await.last_event = Utc::now();
+    let ns = mirror.namespace().unwrap();
+    let docs: Api<ServiceMirror> = Api::namespaced(mirrors.ctx.client.clone(), &ns);
+    tracing::info!(
+        "Reconciling ServiceMirror \"{}\" in {}",
+        mirror.name_any(),
+        ns
+    );
+    finalizer(&docs, SERVICE_MIRROR_FINALIZER, mirror, |event| async {
+        match event {
+            Finalizer::Apply(mirror) => apply(&mirror, &mirrors).await,
+            Finalizer::Cleanup(mirror) => {
+                // the mirrored services are owned by the mirror and garbage collected
+                mirrors.stop(&mirror).await;
+                Ok(Action::await_change())
+            }
+        }
+    })
+    .await
+    .map_err(|e| Error::Finalizer(Box::new(e)))
+}
+
+async fn apply(mirror: &ServiceMirror, mirrors: &Mirrors) -> Result<Action, Error> {
+    let client = mirrors.ctx.client.clone();
+    let ns = mirror.namespace().unwrap();
+    let docs: Api<ServiceMirror> = Api::namespaced(client.clone(), &ns);
+    let remote_ns = mirror.spec.namespace.clone().unwrap_or_else(|| ns.clone());
+    let mut status: ServiceMirrorStatus = mirror.status.clone().unwrap_or_default();
+
+    let (remote, credentials_hash) = match &mirror.spec.cluster_ref {
+        None => (
+            rbac::opted_in(client.clone(), &ns, &remote_ns)
+                .await?
+                .map(|()| client.clone())
+                .map_err(|message| ("Forbidden", message)),
+            String::new(),
+        ),
+        Some(name) => match Api::<RemoteCluster>::namespaced(client.clone(), &ns)
+            .get_opt(name)
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?
+        {
+            Some(cluster) => (
+                cluster::connect(&mirrors.ctx, &cluster)
+                    .await?
+                    .map_err(|message| ("Unreachable", message)),
+                cluster
+                    .status
+                    .and_then(|s| s.credentials_hash)
+                    .unwrap_or_default(),
+            ),
+            None => (
+                Err(("Unreachable", format!("remote cluster `{name}` not found"))),
+                String::new(),
+            ),
+        },
+    };
+    let selector = selector(mirror);
+    let services = match remote {
+        Ok(remote) => {
+            let api = Api::<Service>::namespaced(remote, &remote_ns);
+            let listed = api
+                .list(&ListParams::default().labels(&selector))
+                .await
+                .map_err(|e| {
+                    (
+                        "Unreachable",
+                        format!("unable to list services in `{remote_ns}`: {e}"),
+                    )
+                });
+            if listed.is_ok() {
+                let key = format!("{credentials_hash}|{remote_ns}|{selector}");
+                mirrors.watch(mirror, api, key, &selector).await;
+            }
+            listed
+        }
+        Err(unreachable) => Err(unreachable),
+    };
+    let services = match services {
+        Ok(services) => services,
+        Err((reason, message)) => {
+            mirrors.stop(mirror).await;
+            status::set_condition(
+                &mut status.conditions,
+                status::CONDITION_READY,
+                false,
+                reason,
+                message,
+            );
+            status::patch_status(&docs, mirror, &status).await?;
+            return Ok(Action::requeue(Duration::from_secs(60)));
+        }
+    };
+
+    let children: Api<ForwardedService> = Api::namespaced(client, &ns);
+    let params = PatchParams::apply("port-forward-operator").force();
+    let existing = children
+        .list_metadata(&ListParams::default())
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+    let mut mirrored = Vec::new();
+    let mut conflicts = Vec::new();
+    for service in services {
+        let ports = mirrored_ports(&service);
+        if ports.is_empty() {
+            continue;
+        }
+        let name = child_name(mirror, &service.name_any());
+        if existing
+            .iter()
+            .any(|other| other.name_any() == name && !is_child(mirror, &other.metadata))
+        {
+            // never take over a forward someone else created
+            conflicts.push(name);
+            continue;
+        }
+        let mut child = ForwardedService::new(
+            &name,
+            ForwardedServiceSpec {
+                service: service.name_any(),
+                namespace: Some(remote_ns.clone()),
+                ports,
+                cluster_ref: mirror.spec.cluster_ref.clone(),
+                pooled: mirror.spec.pooled,
+                ..Default::default()
+            },
+        );
+        child.metadata.namespace = Some(ns.clone());
+        child.metadata.labels = Some([(LABEL_MIRROR.to_owned(), mirror.name_any())].into());
+        child.metadata.owner_references = Some(vec![OwnerReference {
+            controller: Some(true),
+            ..owner_reference(mirror)
+        }]);
+        children
+            .patch(&name, &params, &Patch::Apply(&child))
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?;
+        mirrored.push(name);
+    }
+
+    let owned = ListParams::default().labels(&format!("{LABEL_MIRROR}={}", mirror.name_any()));
+    for child in children
+        .list_metadata(&owned)
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?
+    {
+        if is_child(mirror, &child.metadata) && !mirrored.contains(&child.name_any()) {
+            delete_if_exists(children.clone(), &child.name_any()).await?;
+        }
+    }
+
+    status::set_condition(
+        &mut status.conditions,
+        status::CONDITION_CONFLICTING,
+        !conflicts.is_empty(),
+        match conflicts.is_empty() {
+            true => "NoConflicts",
+            false => "NameTaken",
+        },
+        match conflicts.is_empty() {
+            true => "all mirrored services are owned by the mirror".to_owned(),
+            false => format!(
+                "forwarded services not created by the mirror exist: {}",
+                conflicts.join(", ")
+            ),
+        },
+    );
+    status::set_condition(
+        &mut status.conditions,
+        status::CONDITION_READY,
+        true,
+        "Mirrored",
+        format!("mirroring {} services from `{remote_ns}`", mirrored.len()),
+    );
+    status.forwarded_services = mirrored;
+    status::patch_status(&docs, mirror, &status).await?;
+    Ok(Action::requeue(Duration::from_secs(300)))
+}
+
+/// Whether the mirror created the forwarded service with `metadata`
+fn is_child(mirror: &ServiceMirror, metadata: &ObjectMeta) -> bool {
+    let labelled = metadata
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(LABEL_MIRROR))
+        .is_some_and(|name| *name == mirror.name_any());
+    let owned = metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .any(|owner| Some(&owner.uid) == mirror.metadata.uid.as_ref());
+    labelled && owned
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::{Service, ServicePort, ServiceSpec};
+
+    use kube::core::ObjectMeta;
+
+    use super::{child_name, is_child, mirrored_ports, LABEL_MIRROR};
+    use crate::{
+        controller::owner_reference,
+        crd::{ServiceMirror, ServiceMirrorSpec},
+    };
+
+    #[test]
+    fn test_mirrored_ports_skip_udp() {
+        let port = |port: i32, protocol: Option<&str>| ServicePort {
+            port,
+            protocol: protocol.map(str::to_owned),
+            ..Default::default()
+        };
+        let service = Service {
+            spec: Some(ServiceSpec {
+                ports: Some(vec![
+                    port(5432, Some("TCP")),
+                    port(53, Some("UDP")),
+                    port(8080, None),
+                ]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(vec!["5432", "8080"], mirrored_ports(&service));
+    }
+
+    #[test]
+    fn test_child_names_fit_a_service_name() {
+        let mirror = ServiceMirror::new("staging", ServiceMirrorSpec::default());
+        assert_eq!("staging-postgres", child_name(&mirror, "postgres"));
+
+        let long = "a-very-long-remote-service-name-that-is-close-to-the-limit";
+        let name = child_name(&mirror, long);
+        assert_eq!(63, name.len());
+        assert!(name.starts_with("staging-a-very-long"));
+        assert_ne!(name, child_name(&mirror, &format!("{long}x")));
+    }
+
+    #[test]
+    fn test_only_labelled_and_owned_forwards_are_children() {
+        let mut mirror = ServiceMirror::new("staging", ServiceMirrorSpec::default());
+        mirror.metadata.uid = Some("mirror-uid".to_owned());
+        let labels = Some([(LABEL_MIRROR.to_owned(), "staging".to_owned())].into());
+        let owners = Some(vec![owner_reference(&mirror)]);
+
+        let child = ObjectMeta {
+            labels: labels.clone(),
+            owner_references: owners.clone(),
+            ..Default::default()
+        };
+        assert!(is_child(&mirror, &child));
+
+        // created by hand, with or without copying the label
+        assert!(!is_child(&mirror, &ObjectMeta::default()));
+        let labelled = ObjectMeta {
+            labels,
+            ..Default::default()
+        };
+        assert!(!is_child(&mirror, &labelled));
+        let owned = ObjectMeta {
+            owner_references: owners,
+            ..Default::default()
+        };
+        assert!(!is_child(&mirror, &owned));
+    }
+}
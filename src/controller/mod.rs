@@ -6,36 +6,65 @@ use k8s_openapi::{
     api::{
         apps::v1::{Deployment, DeploymentSpec},
         core::v1::{
-            Container, PodSpec, SecretVolumeSource, Service, ServicePort, Volume, VolumeMount,
+            ConfigMap, ConfigMapVolumeSource, Container, ContainerPort, PodSpec, Secret,
+            SecretVolumeSource, Service, ServicePort, Volume, VolumeMount,
         },
     },
     apimachinery::pkg::apis::meta::v1::{LabelSelector, OwnerReference},
 };
 use kube::{
-    api::{ListParams, Patch, PatchParams, PostParams},
+    api::{DeleteParams, ListParams, Patch, PatchParams},
     core::{CustomResourceExt, ObjectMeta},
     runtime::finalizer::Event as Finalizer,
     runtime::{
         controller::Action,
         events::{Event, EventType, Recorder, Reporter},
-        finalizer,
+        finalizer, metadata_watcher,
+        reflector::ObjectRef,
         watcher::Config,
-        Controller,
+        Controller, WatchStreamExt,
     },
     Api, Client, Resource, ResourceExt,
 };
 use tokio::sync::RwLock;
 
+mod cluster;
+mod credentials;
+mod disruption;
+mod failover;
+mod grant;
 pub mod host;
+mod index;
+mod mirror;
+mod ordinals;
+mod pod;
+mod pool;
+mod rbac;
+mod remote;
+mod schedule;
+mod split;
 mod state;
-use self::state::State;
+mod status;
+pub mod tls;
+mod token;
+use self::{credentials::Credentials, state::State};
 use crate::{
-    crd::{ForwardedService, ANNOTATION_GENERATION, FORWARDED_SERVICE_FINALIZER},
+    crd::{
+        CredentialReload, ExpiryAction, ForwardedPort, ForwardedService, ForwardedServiceStatus,
+        RemoteCluster, SecretReferenceGrant, ServiceType, ANNOTATION_GENERATION,
+        ANNOTATION_KUBECONFIG_HASH, FORWARDED_SERVICE_FINALIZER,
+    },
     error::Error,
+    service::CredentialPaths,
 };
 use serde::{de::DeserializeOwned, Serialize};
 
 const KUBE_CONFIG_PATH: &str = "/etc/port-forward-operator/kube";
+const CLUSTER_CONFIG_PATH: &str = "/etc/port-forward-operator/cluster";
+/// Metrics port of a forwarder, unless it forwards that port itself
+const FORWARDER_METRICS_PORT: i32 = 9090;
+/// How long before the end of its lifetime a forward warns unless its expiry sets it
+const DEFAULT_EXPIRY_WARNING: Duration = Duration::from_secs(3600);
 
 pub fn new_state(image: String) -> State {
     State::new(image)
@@ -72,6 +101,12 @@ pub struct Context {
 
     /// Image
     pub image: String,
+    /// Secrets and config maps the credentials of each `ForwardedService` read
+    pub(crate) references: Arc<index::References<ForwardedService>>,
+    /// Secrets and config maps the credentials of each `RemoteCluster` read
+    pub(crate) cluster_references: Arc<index::References<RemoteCluster>>,
+    /// Clients for remote clusters, shared by the reconcilers
+    pub(crate) clients: Arc<credentials::Clients>,
     // Prometheus metrics
     // pub metrics: Metrics,
 }
@@ -87,15 +122,248 @@ pub async fn start(controller_state: State) {
         panic!("crds are not installed: {}", Error::KubeCrd { source: e });
     }
 
-    Controller::new(api, Config::default().any_semantic())
+    let ctx = controller_state.to_context(client.clone());
+    let controller = Controller::new(api, Config::default().any_semantic());
+    let secret_store = controller.store();
+    let config_map_store = controller.store();
+    let cluster_store = controller.store();
+    let grant_store = controller.store();
+    let secret_index = ctx.references.clone();
+    let config_map_index = ctx.references.clone();
+    let forwarded_services = controller
+        .watches_stream(
+            metadata_watcher(Api::<Secret>::all(client.clone()), Config::default())
+                .touched_objects(),
+            move |secret| secret_index.dependents(index::Kind::Secret, &secret, &secret_store),
+        )
+        .watches_stream(
+            metadata_watcher(Api::<ConfigMap>::all(client.clone()), Config::default())
+                .touched_objects(),
+            move |config_map| {
+                config_map_index.dependents(index::Kind::ConfigMap, &config_map, &config_map_store)
+            },
+        )
+        .watches(
+            Api::<SecretReferenceGrant>::all(client.clone()),
+            Config::default(),
+            move |grant| {
+                grant_store
+                    .state()
+                    .into_iter()
+                    .filter(|fs| {
+                        Credentials::from_spec(fs, None)
+                            .map(|c| {
+                                grant::references_namespace(
+                                    &c,
+                                    &grant.namespace().unwrap_or_default(),
+                                )
+                            })
+                            .unwrap_or_default()
+                    })
+                    .map(|fs| ObjectRef::from_obj(fs.as_ref()))
+                    .collect::<Vec<_>>()
+            },
+        )
+        .watches(
+            Api::<RemoteCluster>::all(client.clone()),
+            Config::default(),
+            move |cluster| {
+                cluster_store
+                    .state()
+                    .into_iter()
+                    .filter(|fs| {
+                        let name = cluster.name_any();
+                        fs.namespace() == cluster.namespace()
+                            && (fs.spec.cluster_ref.as_ref() == Some(&name)
+                                || fs
+                                    .spec
+                                    .failover
+                                    .iter()
+                                    .any(|f| f.cluster_refs.contains(&name))
+                                || fs
+                                    .spec
+                                    .traffic_split
+                                    .iter()
+                                    .flat_map(|split| split.clusters.iter())
+                                    .any(|c| c.cluster_ref == name))
+                    })
+                    .map(|fs| ObjectRef::from_obj(fs.as_ref()))
+                    .collect::<Vec<_>>()
+            },
+        )
         .shutdown_on_signal()
-        .run(reconcile, error_policy, controller_state.to_context(client))
+        .run(reconcile, error_policy, ctx.clone())
         .filter_map(|x| async move { std::result::Result::ok(x) })
-        .for_each(|_| futures::future::ready(()))
-        .await;
+        .for_each(|_| futures::future::ready(()));
+    futures::join!(
+        forwarded_services,
+        cluster::run(client.clone(), ctx.clone()),
+        mirror::run(client, ctx)
+    );
+}
+
+/// Where the forwarder finds credentials mounted at `kube_config_path` and
+/// `cluster_config_path`. The forwarder falls back to its service account without any.
+pub(crate) fn credential_paths(
+    credentials: &Credentials,
+    kube_config_path: &str,
+    cluster_config_path: &str,
+) -> CredentialPaths {
+    match credentials {
+        Credentials::InCluster => CredentialPaths::default(),
+        Credentials::KubeConfig(_) | Credentials::CopiedKubeConfig { .. } => {
+            let reference = credentials.kube_config().expect("kubeconfig credentials");
+            CredentialPaths {
+                kubeconfig: Some(format!("{}/{}", kube_config_path, reference.key_any())),
+                kube_context: Some(reference.context.clone()),
+                kube_user: reference.user.clone(),
+                kube_cluster: reference.cluster.clone(),
+                ..Default::default()
+            }
+        }
+        Credentials::Structured(_) | Credentials::TokenRequest { .. } => {
+            let reference = credentials.structured().expect("structured credentials");
+            let cluster_path = match reference.config_map {
+                Some(_) => cluster_config_path,
+                None => kube_config_path,
+            };
+            CredentialPaths {
+                server_file: Some(format!("{}/{}", cluster_path, reference.server_key_any())),
+                certificate_authority_file: Some(format!(
+                    "{}/{}",
+                    cluster_path,
+                    reference.certificate_authority_key_any()
+                )),
+                token_file: Some(format!(
+                    "{}/{}",
+                    kube_config_path,
+                    reference.token_key_any()
+                )),
+                client_certificate_file: Some(format!(
+                    "{}/{}",
+                    kube_config_path,
+                    reference.client_certificate_key_any()
+                )),
+                client_key_file: Some(format!(
+                    "{}/{}",
+                    kube_config_path,
+                    reference.client_key_key_any()
+                )),
+                ..Default::default()
+            }
+        }
+    }
+}
+
+/// First port from `FORWARDER_METRICS_PORT` on that none of `forwarded_ports` listens
+/// on, so forwarding the metrics port locally does not clash with the metrics server
+fn metrics_port(forwarded_ports: &[ForwardedPort]) -> i32 {
+    let mut port = FORWARDER_METRICS_PORT;
+    while forwarded_ports.iter().any(|p| i32::from(p.port) == port) {
+        port += 1;
+    }
+    port
+}
+
+/// Arguments pointing the forwarder at the mounted credentials
+pub(crate) fn credential_args(credentials: &Credentials, args: &mut Vec<String>) {
+    credential_paths(credentials, KUBE_CONFIG_PATH, CLUSTER_CONFIG_PATH).args(args);
+}
+
+/// Mounts of the credentials secret and cluster config map into the forwarder
+pub(crate) fn credential_volumes(
+    secret: Option<&str>,
+    config_map: Option<&str>,
+) -> (Vec<VolumeMount>, Vec<Volume>) {
+    mount_credentials(
+        secret,
+        config_map,
+        "",
+        KUBE_CONFIG_PATH,
+        CLUSTER_CONFIG_PATH,
+    )
+}
+
+/// Mounts of the credentials at the given paths, with volume names starting with
+/// `prefix`
+pub(crate) fn mount_credentials(
+    secret: Option<&str>,
+    config_map: Option<&str>,
+    prefix: &str,
+    kube_config_path: &str,
+    cluster_config_path: &str,
+) -> (Vec<VolumeMount>, Vec<Volume>) {
+    let mut volume_mounts = Vec::new();
+    let mut volumes = Vec::new();
+    if let Some(secret) = secret {
+        volume_mounts.push(VolumeMount {
+            mount_path: kube_config_path.to_owned(),
+            name: format!("{prefix}kubeconfig"),
+            read_only: Some(true),
+            ..Default::default()
+        });
+        volumes.push(Volume {
+            name: format!("{prefix}kubeconfig"),
+            secret: Some(SecretVolumeSource {
+                optional: Some(false),
+                secret_name: Some(secret.to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+    if let Some(config_map) = config_map {
+        volume_mounts.push(VolumeMount {
+            mount_path: cluster_config_path.to_owned(),
+            name: format!("{prefix}cluster"),
+            read_only: Some(true),
+            ..Default::default()
+        });
+        volumes.push(Volume {
+            name: format!("{prefix}cluster"),
+            config_map: Some(ConfigMapVolumeSource {
+                optional: Some(false),
+                name: Some(config_map.to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+    (volume_mounts, volumes)
 }
 
-fn error_policy(_: Arc<ForwardedService>, error: &Error, _: Arc<Context>) -> Action {
+fn is_headless(service: &Service) -> bool {
+    service
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.cluster_ip.as_deref())
+        == Some("None")
+}
+
+/// References `owner` from the objects generated for it, so they are garbage collected
+pub(crate) fn owner_reference<K: Resource<DynamicType = ()>>(owner: &K) -> OwnerReference {
+    OwnerReference {
+        api_version: K::api_version(&()).into_owned(),
+        controller: Some(false),
+        kind: K::kind(&()).into_owned(),
+        name: owner.name_any(),
+        uid: owner.meta().uid.clone().unwrap(),
+        ..Default::default()
+    }
+}
+
+pub(crate) async fn delete_if_exists<T>(api: Api<T>, name: &str) -> Result<(), Error>
+where
+    T: Resource + Clone + DeserializeOwned + std::fmt::Debug,
+{
+    match api.delete(name, &DeleteParams::default()).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(r)) if r.code == 404 => Ok(()),
+        Err(e) => Err(Error::Kubernetes { source: e }),
+    }
+}
+
+fn error_policy<K, C>(_: Arc<K>, error: &Error, _: Arc<C>) -> Action {
     tracing::warn!("reconcile failed: {:?}", error);
     // ctx.metrics.reconcile_failure(&doc, error);
     Action::requeue(Duration::from_secs(5 * 60))
@@ -118,10 +386,12 @@ async fn reconcile(svc: Arc<ForwardedService>, ctx: Arc<Context>) -> Result<Acti
         }
     })
     .await
-    .map_err(|e| Error::FinalizerError(Box::new(e)))
+    .map_err(|e| Error::Finalizer(Box::new(e)))
 }
 
 impl ForwardedService {
+    /// Applies `obj` unless it exists and `convert` finds nothing to update. The whole
+    /// object is applied, so fields left out of it are removed from the existing one.
     async fn create_or_update<T>(
         &self,
         api: &Api<T>,
@@ -132,35 +402,27 @@ impl ForwardedService {
         T: Resource + Serialize + DeserializeOwned + Clone + std::fmt::Debug,
     {
         let name = &obj.name_any();
-        match api.create(&PostParams::default(), &obj).await {
-            Ok(res) => Ok(res),
-            Err(e) => match e {
-                kube::Error::Api(r) if r.code == 409 => {
-                    let data = api
-                        .get(name)
-                        .await
-                        .map_err(|k| Error::Kubernetes { source: k })?;
-
-                    if convert(self, &data, &obj) {
-                        tracing::info!(
-                            "updating object {}/{} due to differences",
-                            data.namespace().unwrap(),
-                            data.name_any()
-                        );
-                        api.patch(
-                            name,
-                            &PatchParams::apply("port-forward-operator"),
-                            &Patch::Merge(obj),
-                        )
-                        .await
-                        .map_err(|k| Error::Kubernetes { source: k })
-                    } else {
-                        Ok(obj)
-                    }
-                }
-                _ => Err(Error::Kubernetes { source: e }),
-            },
+        let existing = api
+            .get_opt(name)
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?;
+        if let Some(data) = existing {
+            if !convert(self, &data, &obj) {
+                return Ok(obj);
+            }
+            tracing::info!(
+                "updating object {}/{} due to differences",
+                data.namespace().unwrap_or_default(),
+                data.name_any()
+            );
         }
+        api.patch(
+            name,
+            &PatchParams::apply("port-forward-operator").force(),
+            &Patch::Apply(&obj),
+        )
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })
     }
 
     fn compare_generation<T: Resource>(fs: &Self, actual: &T, _: &T) -> bool {
@@ -171,6 +433,57 @@ impl ForwardedService {
         }
     }
 
+    /// Ports of a service as far as the controller sets them
+    fn service_ports(service: &Service) -> Vec<ServicePort> {
+        service
+            .spec
+            .iter()
+            .flat_map(|spec| spec.ports.iter().flatten())
+            .map(|port| ServicePort {
+                name: port.name.clone(),
+                port: port.port,
+                target_port: port.target_port.clone(),
+                app_protocol: port.app_protocol.clone(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Records the outcome of the credential inspection on the status
+    fn check_credentials(
+        &self,
+        inspected: Result<Option<DateTime<Utc>>, (&'static str, String)>,
+        status: &mut ForwardedServiceStatus,
+    ) -> Result<(), String> {
+        let expiry =
+            status::check_credentials(inspected, &mut status.conditions, &mut status.expires_at)?;
+        if let Some(expiry) = expiry {
+            metrics::gauge!(
+                "forwardedservice_credentials_expiry_timestamp_seconds",
+                expiry.timestamp() as f64,
+                "namespace" => self.namespace().unwrap_or_default(),
+                "name" => self.name_any()
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads the kubeconfig hash from wherever the reload strategy stamps it
+    fn credentials_hash(&self, deployment: &Deployment) -> Option<String> {
+        let annotations = match self.spec.credential_reload.unwrap_or_default() {
+            CredentialReload::InPlace => deployment.metadata.annotations.as_ref(),
+            CredentialReload::Restart => deployment
+                .spec
+                .as_ref()?
+                .template
+                .metadata
+                .as_ref()?
+                .annotations
+                .as_ref(),
+        };
+        annotations?.get(ANNOTATION_KUBECONFIG_HASH).cloned()
+    }
+
     async fn reconcile(&self, ctx: Arc<Context>) -> Result<Action, Error> {
         let client = ctx.client.clone();
         let recorder = ctx.diagnostics.read().await.recorder(client.clone(), self);
@@ -178,10 +491,684 @@ impl ForwardedService {
         let name = self.name_any();
         let deployments: Api<Deployment> = Api::namespaced(client.clone(), &ns);
         let services: Api<Service> = Api::namespaced(client.clone(), &ns);
-        let (service, pod) = self.create_service_and_deployment(ctx.as_ref())?;
+        let docs: Api<ForwardedService> = Api::namespaced(client.clone(), &ns);
+        // credentials of a referenced cluster are followed through the cluster
+        ctx.references.set(
+            self,
+            Credentials::from_spec(self, None)
+                .map(|c| index::referenced(&c, &ns))
+                .unwrap_or_default(),
+        );
+        if let Some(action) = self.expire(&docs, &recorder).await? {
+            return Ok(action);
+        }
+        let cluster = match &self.spec.cluster_ref {
+            Some(cluster) => Api::<RemoteCluster>::namespaced(client.clone(), &ns)
+                .get_opt(cluster)
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?,
+            None => None,
+        };
+        let target = self.spec.namespace.clone().unwrap_or_else(|| ns.clone());
+        let mut token_expiry = None;
+        let (credentials, inspection) = match Credentials::from_spec(self, cluster.as_ref()) {
+            Ok(credentials) => {
+                // copies and tokens for a referenced cluster are kept by its own reconciler
+                let copied = match cluster {
+                    Some(_) => {
+                        grant::prune(client.clone(), self, &[]).await?;
+                        Ok(())
+                    }
+                    None => grant::sync(client.clone(), self, &credentials).await?,
+                };
+                let minted = match (copied, &credentials) {
+                    (Err(invalid), _) => Some(Err(invalid)),
+                    (Ok(()), Credentials::TokenRequest { .. }) if cluster.is_none() => {
+                        Some(token::refresh(client.clone(), self, &target, &credentials).await?)
+                    }
+                    _ => None,
+                };
+                let inspection = match minted {
+                    Some(Err((reason, message))) => {
+                        credentials::Inspection::invalid(None, reason, message)
+                    }
+                    minted => {
+                        token_expiry = minted.and_then(Result::ok);
+                        credentials::inspect(client.clone(), &ns, &credentials).await?
+                    }
+                };
+                (Some(credentials), inspection)
+            }
+            Err(message) => (
+                None,
+                credentials::Inspection::invalid(None, "Invalid", message),
+            ),
+        };
+
+        let mut status = self.status.clone().unwrap_or_default();
+        match self.validate_service_name() {
+            Ok(()) => status::set_condition(
+                &mut status.conditions,
+                status::CONDITION_SERVICE_NAME_VALID,
+                true,
+                "Valid",
+                format!("service `{}` can be created", self.service_name()),
+            ),
+            Err(message) => {
+                // the service under the previous name is kept until the name is fixed
+                status::set_condition(
+                    &mut status.conditions,
+                    status::CONDITION_SERVICE_NAME_VALID,
+                    false,
+                    "Invalid",
+                    message.clone(),
+                );
+                status::patch_status(&docs, self, &status).await?;
+                recorder
+                    .publish(Event {
+                        type_: EventType::Warning,
+                        reason: "InvalidServiceName".into(),
+                        note: Some(message),
+                        action: "Validating".into(),
+                        secondary: None,
+                    })
+                    .await
+                    .map_err(|e| Error::Kubernetes { source: e })?;
+                return Ok(Action::await_change());
+            }
+        }
+        let previous_service = status.service_name.clone();
+        let service_name = self.service_name();
+        status.service_name = service_name.clone();
+        status.token_expires_at = token_expiry;
+        let checked = self.check_credentials(inspection.result.clone(), &mut status);
+        status::patch_status(&docs, self, &status).await?;
+        if let Err(message) = checked {
+            recorder
+                .publish(Event {
+                    type_: EventType::Warning,
+                    reason: "InvalidCredentials".into(),
+                    note: Some(message),
+                    action: "Validating".into(),
+                    secondary: None,
+                })
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+            return Ok(Action::requeue(Duration::from_secs(60)));
+        }
+        let credentials = credentials.expect("validated credentials");
+        match credentials {
+            Credentials::InCluster => {
+                if let Err(message) = rbac::opted_in(client.clone(), &ns, &target).await? {
+                    rbac::revoke_except(client.clone(), self, None).await?;
+                    status::set_condition(
+                        &mut status.conditions,
+                        status::CONDITION_PORT_FORWARD_ALLOWED,
+                        false,
+                        "Forbidden",
+                        message.clone(),
+                    );
+                    status::patch_status(&docs, self, &status).await?;
+                    recorder
+                        .publish(Event {
+                            type_: EventType::Warning,
+                            reason: "Forbidden".into(),
+                            note: Some(message),
+                            action: "Granting".into(),
+                            secondary: None,
+                        })
+                        .await
+                        .map_err(|e| Error::Kubernetes { source: e })?;
+                    return Ok(Action::requeue(Duration::from_secs(300)));
+                }
+                rbac::grant(client.clone(), self, &target).await?
+            }
+            _ => rbac::revoke_except(client.clone(), self, None).await?,
+        }
+
+        self.report_suspension(&mut status, &recorder).await?;
+
+        let mut requeue = Duration::from_secs(300);
+        let now = Utc::now();
+        let in_schedule = self.report_schedule(&mut status, &recorder, now).await?;
+        let paused = self.is_suspended() || !in_schedule;
+        if let Some(next) = status.next_transition {
+            // scaled right after the boundary rather than on the next periodic run
+            let until = (next - now).to_std().unwrap_or_default();
+            requeue = requeue.min(until + Duration::from_secs(1));
+        }
+        if let Some(until) = self.report_lifetime(&mut status, &recorder, now).await? {
+            requeue = requeue.min(until);
+        }
+        let remote = ctx
+            .clients
+            .get(&client, &ns, &inspection, &credentials)
+            .await;
+        let checks = remote::check(
+            remote,
+            &credentials,
+            &target,
+            &self.target(),
+            &rbac::ServiceAccountUser::new(&ns, &rbac::service_account_name(self)),
+        )
+        .await;
+        let target_checked = checks.record(&mut status.conditions);
+        let forwarded_ports = match self.spec.ports.is_empty() {
+            true => {
+                let discovered = match &checks.ports {
+                    Some(ports) if ports.is_empty() => {
+                        Err(format!("{} in `{target}` has no TCP ports", self.target()))
+                    }
+                    Some(ports) => Ok(ports.clone()),
+                    None => Err(checks.target_found.clone().unwrap_or_else(|e| e).1),
+                };
+                match discovered {
+                    Ok(ports) => {
+                        status::set_condition(
+                            &mut status.conditions,
+                            status::CONDITION_PORTS_RESOLVED,
+                            true,
+                            "Discovered",
+                            format!("{} ports read from the remote service", ports.len()),
+                        );
+                        // port changes on the remote service are picked up on the next run
+                        requeue = requeue.min(Duration::from_secs(60));
+                        ports
+                    }
+                    Err(message) => {
+                        status::set_condition(
+                            &mut status.conditions,
+                            status::CONDITION_PORTS_RESOLVED,
+                            false,
+                            "Unresolved",
+                            message.clone(),
+                        );
+                        status::patch_status(&docs, self, &status).await?;
+                        recorder
+                            .publish(Event {
+                                type_: EventType::Warning,
+                                reason: "PortsUnresolved".into(),
+                                note: Some(message),
+                                action: "Discovering".into(),
+                                secondary: None,
+                            })
+                            .await
+                            .map_err(|e| Error::Kubernetes { source: e })?;
+                        return Ok(Action::requeue(Duration::from_secs(60)));
+                    }
+                }
+            }
+            false => self.forwarded_ports(),
+        };
+        let ordinals = match self.has_ordinal_services() {
+            true => Some(checks.replicas.unwrap_or_default()),
+            false => None,
+        };
+        if ordinals.is_some() || status.ordinals.is_some() {
+            ordinals::sync(client.clone(), self, &target, &forwarded_ports, ordinals).await?;
+        }
+        if ordinals.is_some() {
+            // scaling of the stateful set is picked up on the next run
+            requeue = requeue.min(Duration::from_secs(60));
+        }
+        status.ordinals = ordinals;
+        status.ports = forwarded_ports.clone();
+        status::patch_status(&docs, self, &status).await?;
+        // a paused forward is expected to lose its target, as in a maintenance window
+        if let (Err(message), false) = (target_checked, paused) {
+            // the forwarder is kept in place, so it recovers once the target appears
+            recorder
+                .publish(Event {
+                    type_: EventType::Warning,
+                    reason: "RemoteTargetUnavailable".into(),
+                    note: Some(message),
+                    action: "Checking".into(),
+                    secondary: None,
+                })
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+            requeue = requeue.min(Duration::from_secs(60));
+        }
+
+        let pool = match self.is_pooled() && !forwarded_ports.is_empty() {
+            true => pool::pool_name(&credentials),
+            false => None,
+        };
+        // leaving a pool, also for another one, has to drop this service from its config
+        if let Some(previous) = status.pool.as_ref().filter(|p| pool.as_ref() != Some(*p)) {
+            pool::leave(ctx.as_ref(), self, previous).await?;
+        }
+        let pool = match pool {
+            Some(pool) => {
+                let allocation = pool::join(
+                    ctx.as_ref(),
+                    self,
+                    &pool,
+                    &credentials,
+                    inspection.hash.clone(),
+                    &forwarded_ports,
+                    paused,
+                )
+                .await?;
+                Some((pool, allocation))
+            }
+            None => None,
+        };
+        let pool_name = pool.as_ref().map(|(pool, _)| pool.clone());
+        let unpoolable = self.unpoolable_settings();
+        match (self.spec.pooled.unwrap_or_default(), &pool_name) {
+            (false, _) => status
+                .conditions
+                .retain(|c| c.type_ != status::CONDITION_POOLED),
+            (true, Some(pool)) => status::set_condition(
+                &mut status.conditions,
+                status::CONDITION_POOLED,
+                true,
+                "Pooled",
+                format!("served by the forwarder of pool `{pool}`"),
+            ),
+            (true, None) if !unpoolable.is_empty() => status::set_condition(
+                &mut status.conditions,
+                status::CONDITION_POOLED,
+                false,
+                "DedicatedSettings",
+                format!(
+                    "served by a dedicated forwarder, since a pool ignores `{}`",
+                    unpoolable.join("`, `")
+                ),
+            ),
+            (true, None) => status::set_condition(
+                &mut status.conditions,
+                status::CONDITION_POOLED,
+                false,
+                "NotPoolable",
+                "forwards within the local cluster or without ports are not pooled",
+            ),
+        }
+        if status.pool != pool_name {
+            // a merge patch cannot drop the previous selector label
+            delete_if_exists(services.clone(), &service_name).await?;
+            status.pool = pool_name;
+            status::patch_status(&docs, self, &status).await?;
+        }
+
+        let names: Vec<&String> = match (&self.spec.traffic_split, &self.spec.failover) {
+            (Some(split), _) => split.clusters.iter().map(|c| &c.cluster_ref).collect(),
+            (None, Some(failover)) => failover.cluster_refs.iter().collect(),
+            (None, None) => Vec::new(),
+        };
+        let (others, missing) = failover::clusters(client.clone(), self, names).await?;
+        for name in missing {
+            recorder
+                .publish(Event {
+                    type_: EventType::Warning,
+                    reason: "ClusterNotFound".into(),
+                    note: Some(format!("remote cluster `{name}` not found")),
+                    action: "Checking".into(),
+                    secondary: None,
+                })
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+        }
+        split::sync(client.clone(), self).await?;
+
+        let (service, mut pod) = self.create_service_and_deployment(
+            ctx.as_ref(),
+            &credentials,
+            inspection.hash,
+            &forwarded_ports,
+            pool.as_ref(),
+            ordinals.is_some(),
+            &others,
+        )?;
+        if !previous_service.is_empty() && previous_service != service_name {
+            delete_if_exists(services.clone(), &previous_service).await?;
+        }
+        let existing = services
+            .get_opt(&service_name)
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?;
+        if existing.is_some_and(|existing| is_headless(&existing) != is_headless(&service)) {
+            // the cluster IP of a service cannot change
+            delete_if_exists(services.clone(), &service_name).await?;
+        }
         let _ = self
-            .create_or_update(&deployments, pod, |fs, actual, expected| {
-                if Self::compare_generation(fs, actual, expected) {
+            .create_or_update(&services, service, |fs, actual, expected| {
+                Self::compare_generation(fs, actual, expected)
+                    || Self::service_ports(actual) != Self::service_ports(expected)
+            })
+            .await?;
+        match pool {
+            Some(_) => {
+                delete_if_exists(deployments, &name).await?;
+                disruption::sync(client.clone(), self, 0).await?;
+            }
+            None => {
+                let replicas = match paused {
+                    true => 0,
+                    false => self.replicas(),
+                };
+                if let Some(spec) = pod.spec.as_mut() {
+                    spec.replicas = Some(replicas);
+                }
+                self.update_deployment(&deployments, pod, &credentials, &recorder)
+                    .await?;
+                disruption::sync(client.clone(), self, replicas).await?;
+            }
+        }
+
+        if self.spec.failover.is_some() || status.active_cluster.is_some() {
+            self.report_failover(client.clone(), &docs, &mut status, &recorder)
+                .await?;
+            requeue = requeue.min(Duration::from_secs(60));
+        }
+
+        if !paused {
+            recorder
+                .publish(Event {
+                    type_: EventType::Normal,
+                    reason: "ForwardingRequest".into(),
+                    note: Some(format!("Forwarding `{name}`")),
+                    action: "Forwarding".into(),
+                    secondary: None,
+                })
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+        }
+
+        if let (Credentials::TokenRequest { reference, .. }, Some(expiry)) =
+            (&credentials, token_expiry)
+        {
+            requeue = token::requeue_after(reference, expiry, requeue);
+        }
+        Ok(Action::requeue(requeue))
+    }
+
+    /// Sets the `Suspended` condition, with an event when forwarding was turned off or
+    /// back on
+    async fn report_suspension(
+        &self,
+        status: &mut ForwardedServiceStatus,
+        recorder: &Recorder,
+    ) -> Result<(), Error> {
+        let suspended = self.is_suspended();
+        let was_suspended = status::is_true(&status.conditions, status::CONDITION_SUSPENDED);
+        match suspended {
+            true => status::set_condition(
+                &mut status.conditions,
+                status::CONDITION_SUSPENDED,
+                true,
+                "Suspended",
+                "forwarder scaled to zero, the local service is kept",
+            ),
+            false if was_suspended => status::set_condition(
+                &mut status.conditions,
+                status::CONDITION_SUSPENDED,
+                false,
+                "Resumed",
+                format!("forwarder scaled back to {} replicas", self.replicas()),
+            ),
+            false => return Ok(()),
+        }
+        if suspended == was_suspended {
+            return Ok(());
+        }
+        let (reason, note) = match suspended {
+            true => (
+                "Suspended",
+                "Forwarding suspended, scaling the forwarder to zero",
+            ),
+            false => ("Resumed", "Forwarding resumed"),
+        };
+        recorder
+            .publish(Event {
+                type_: EventType::Normal,
+                reason: reason.into(),
+                note: Some(note.into()),
+                action: "Scaling".into(),
+                secondary: None,
+            })
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })
+    }
+
+    /// Deletes or suspends the forward once its lifetime ended. Returns the action of a
+    /// reconcile that ends here.
+    async fn expire(
+        &self,
+        docs: &Api<ForwardedService>,
+        recorder: &Recorder,
+    ) -> Result<Option<Action>, Error> {
+        let Some(end) = self.lifetime_end().filter(|end| *end <= Utc::now()) else {
+            return Ok(None);
+        };
+        let action = self
+            .spec
+            .expiry
+            .as_ref()
+            .and_then(|expiry| expiry.action)
+            .unwrap_or_default();
+        if action == ExpiryAction::Suspend && self.is_suspended() {
+            return Ok(None);
+        }
+        let note = match action {
+            ExpiryAction::Delete => format!("Expired at {end}, deleting the forward"),
+            ExpiryAction::Suspend => format!("Expired at {end}, suspending the forward"),
+        };
+        recorder
+            .publish(Event {
+                type_: EventType::Warning,
+                reason: "Expired".into(),
+                note: Some(note),
+                action: "Expiring".into(),
+                secondary: None,
+            })
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?;
+        let name = self.name_any();
+        match action {
+            ExpiryAction::Delete => {
+                docs.delete(&name, &DeleteParams::default())
+                    .await
+                    .map_err(|e| Error::Kubernetes { source: e })?;
+            }
+            ExpiryAction::Suspend => {
+                let patch = serde_json::json!({ "spec": { "suspend": true } });
+                docs.patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+                    .await
+                    .map_err(|e| Error::Kubernetes { source: e })?;
+            }
+        }
+        Ok(Some(Action::await_change()))
+    }
+
+    /// Sets the remaining lifetime and the `Expiring` condition, with a warning event
+    /// once the end is within the warning lead time. Returns when to check again.
+    async fn report_lifetime(
+        &self,
+        status: &mut ForwardedServiceStatus,
+        recorder: &Recorder,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Duration>, Error> {
+        let Some(end) = self.lifetime_end() else {
+            status.lifetime_ends_at = None;
+            status
+                .conditions
+                .retain(|c| c.type_ != status::CONDITION_EXPIRING);
+            return Ok(None);
+        };
+        let remaining = (end - now).to_std().unwrap_or_default();
+        status.lifetime_ends_at = Some(end);
+        let warning = self
+            .spec
+            .expiry
+            .as_ref()
+            .and_then(|expiry| expiry.warning_seconds)
+            .map_or(DEFAULT_EXPIRY_WARNING, Duration::from_secs);
+        let expiring = remaining <= warning;
+        let was_expiring = status::is_true(&status.conditions, status::CONDITION_EXPIRING);
+        status::set_condition(
+            &mut status.conditions,
+            status::CONDITION_EXPIRING,
+            expiring,
+            match expiring {
+                true => "ExpiresSoon",
+                false => "Scheduled",
+            },
+            format!("the forward expires at {end}"),
+        );
+        if expiring && !was_expiring {
+            recorder
+                .publish(Event {
+                    type_: EventType::Warning,
+                    reason: "ExpiresSoon".into(),
+                    note: Some(format!(
+                        "Expires at {end}, extend `spec.expiry` to keep the forward"
+                    )),
+                    action: "Expiring".into(),
+                    secondary: None,
+                })
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+        }
+        if remaining.is_zero() {
+            // expired and already suspended
+            return Ok(None);
+        }
+        // wakes up when the warning is due and again when the forward expires
+        let until = match expiring {
+            true => remaining,
+            false => remaining - warning,
+        };
+        Ok(Some(until + Duration::from_secs(1)))
+    }
+
+    /// Sets the `InSchedule` condition and the next transition of the schedule, with an
+    /// event when a window opened or closed. Returns whether the forwarder runs now.
+    async fn report_schedule(
+        &self,
+        status: &mut ForwardedServiceStatus,
+        recorder: &Recorder,
+        now: DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let Some(schedule) = &self.spec.schedule else {
+            status.next_transition = None;
+            status
+                .conditions
+                .retain(|c| c.type_ != status::CONDITION_IN_SCHEDULE);
+            return Ok(true);
+        };
+        let window = schedule::evaluate(schedule, now);
+        status.next_transition = window.as_ref().ok().and_then(|w| w.next_transition);
+        let (open, reason, message) = match window {
+            Ok(window) if window.open => {
+                (true, "WindowOpen", "within a scheduled window".to_owned())
+            }
+            Ok(_) => (
+                false,
+                "WindowClosed",
+                "outside of the scheduled windows, forwarder scaled to zero".to_owned(),
+            ),
+            Err(message) => (false, "InvalidSchedule", message),
+        };
+        let changed = !status.conditions.iter().any(|c| {
+            c.type_ == status::CONDITION_IN_SCHEDULE && c.reason.as_deref() == Some(reason)
+        });
+        status::set_condition(
+            &mut status.conditions,
+            status::CONDITION_IN_SCHEDULE,
+            open,
+            reason,
+            message.clone(),
+        );
+        if changed {
+            recorder
+                .publish(Event {
+                    type_: match reason {
+                        "InvalidSchedule" => EventType::Warning,
+                        _ => EventType::Normal,
+                    },
+                    reason: reason.into(),
+                    note: Some(message),
+                    action: "Scheduling".into(),
+                    secondary: None,
+                })
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+        }
+        Ok(open)
+    }
+
+    /// Records the cluster the forwarder is active on, with an event when it changed
+    async fn report_failover(
+        &self,
+        client: Client,
+        docs: &Api<ForwardedService>,
+        status: &mut ForwardedServiceStatus,
+        recorder: &Recorder,
+    ) -> Result<(), Error> {
+        // a traffic split takes precedence over failover
+        let failing_over = self.spec.failover.is_some() && self.spec.traffic_split.is_none();
+        let reported = match failing_over {
+            true => failover::status(client, self).await,
+            false => None,
+        };
+        let Some(reported) = reported else {
+            if !failing_over {
+                status.active_cluster = None;
+                status.failovers = None;
+                status::patch_status(docs, self, status).await?;
+            }
+            return Ok(());
+        };
+        if status
+            .active_cluster
+            .as_ref()
+            .is_some_and(|active| *active != reported.active)
+        {
+            let primary = reported.active == failover::primary_name(self);
+            recorder
+                .publish(Event {
+                    type_: match primary {
+                        true => EventType::Normal,
+                        false => EventType::Warning,
+                    },
+                    reason: match primary {
+                        true => "FailedBack".into(),
+                        false => "FailedOver".into(),
+                    },
+                    note: Some(format!(
+                        "Forwarding new connections through cluster `{}`",
+                        reported.active
+                    )),
+                    action: "Forwarding".into(),
+                    secondary: None,
+                })
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+        }
+        status.active_cluster = Some(reported.active);
+        status.failovers = Some(reported.failovers);
+        status::patch_status(docs, self, status).await
+    }
+
+    /// Applies the dedicated forwarder and reports credential rotations rolling it
+    async fn update_deployment(
+        &self,
+        deployments: &Api<Deployment>,
+        pod: Deployment,
+        credentials: &Credentials<'_>,
+        recorder: &Recorder,
+    ) -> Result<(), Error> {
+        let mut previous_hash = None;
+        let deployment = self
+            .create_or_update(deployments, pod, |fs, actual, expected| {
+                previous_hash = fs.credentials_hash(actual);
+                if Self::compare_generation(fs, actual, expected)
+                    || previous_hash != fs.credentials_hash(expected)
+                    || actual.spec.as_ref().and_then(|s| s.replicas)
+                        != expected.spec.as_ref().and_then(|s| s.replicas)
+                {
                     true
                 } else {
                     let actual_spec = actual
@@ -201,7 +1188,7 @@ impl ForwardedService {
                         .as_ref()
                         .unwrap();
                     match actual_spec.containers[0].image != expected_spec.containers[0].image
-                        || actual_spec.containers[0].args == expected_spec.containers[0].args
+                        || actual_spec.containers[0].args != expected_spec.containers[0].args
                     {
                         true => true,
                         false => false,
@@ -209,74 +1196,106 @@ impl ForwardedService {
                 }
             })
             .await?;
-        let _ = self
-            .create_or_update(&services, service, Self::compare_generation)
-            .await?;
-
-        recorder
-            .publish(Event {
-                type_: EventType::Normal,
-                reason: "ForwardingRequest".into(),
-                note: Some(format!("Forwarding `{name}`")),
-                action: "Forwarding".into(),
-                secondary: None,
-            })
-            .await
-            .map_err(|e| Error::Kubernetes { source: e })?;
 
-        Ok(Action::requeue(Duration::from_secs(300)))
+        if previous_hash.is_some() && previous_hash != self.credentials_hash(&deployment) {
+            let note = match self.spec.credential_reload.unwrap_or_default() {
+                CredentialReload::InPlace => "forwarder reloads credentials",
+                CredentialReload::Restart => "rolling forwarder",
+            };
+            recorder
+                .publish(Event {
+                    type_: EventType::Normal,
+                    reason: "CredentialsRotated".into(),
+                    note: Some(format!(
+                        "Credentials in secret `{}` changed, {note}",
+                        credentials.secret().unwrap_or_default()
+                    )),
+                    action: "Rolling".into(),
+                    secondary: None,
+                })
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+        }
+        Ok(())
     }
 
-    fn add_vector_args(&self, args: &mut Vec<String>) {
+    fn add_vector_args(
+        &self,
+        credentials: &Credentials,
+        metrics_port: i32,
+        args: &mut Vec<String>,
+    ) {
         let ns = self.spec.namespace.clone();
-        args.push("--kubeconfig".to_owned());
-        args.push(format!(
-            "{}/{}",
-            KUBE_CONFIG_PATH,
-            self.spec.kube_config.key_any()
-        ));
-        args.push("--context".to_owned());
-        args.push(self.spec.kube_config.context.clone());
-        if let Some(kube_user) = &self.spec.kube_config.user {
-            args.push("--user".to_owned());
-            args.push(kube_user.clone());
-        }
-
-        if let Some(kube_cluster) = &self.spec.kube_config.cluster {
-            args.push("--cluster".to_owned());
-            args.push(kube_cluster.clone());
-        }
-
+        args.push("service".to_owned());
+        credential_args(credentials, args);
         args.push("--namespace".to_owned());
         args.push(ns.or(self.namespace()).unwrap());
-        args.push("port-forward".to_owned());
-        args.push("--address".into());
-        args.push("0.0.0.0".into());
-        args.push(format!("svc/{}", self.spec.service.clone()));
+        let target = self.target();
+        args.push("--kind".to_owned());
+        args.push(format!("{:?}", target.kind).to_lowercase());
+        if let Some(name) = &target.name {
+            args.push("--name".to_owned());
+            args.push(name.clone());
+        }
+        if !target.labels.is_empty() {
+            args.push("--selector".to_owned());
+            args.push(target.selector());
+        }
+        if let Some(drain_seconds) = target.drain_seconds {
+            args.push("--drain-seconds".to_owned());
+            args.push(drain_seconds.to_string());
+        }
+        args.push("--metrics-address".to_owned());
+        args.push(format!("0.0.0.0:{metrics_port}"));
     }
 
-    fn create_service_and_deployment(&self, ctx: &Context) -> Result<(Service, Deployment), Error> {
+    #[allow(clippy::too_many_arguments)]
+    fn create_service_and_deployment(
+        &self,
+        ctx: &Context,
+        credentials: &Credentials,
+        config_hash: Option<String>,
+        forwarded_ports: &[ForwardedPort],
+        pool: Option<&(String, pool::Allocation)>,
+        ordinal_services: bool,
+        others: &[RemoteCluster],
+    ) -> Result<(Service, Deployment), Error> {
         let mut labels: std::collections::BTreeMap<String, String> =
             std::collections::BTreeMap::new();
         let mut ports: Vec<ServicePort> = Vec::new();
-        let mut args: Vec<String> = Vec::with_capacity(self.spec.ports.len() + 6);
-        self.add_vector_args(&mut args);
-        for port in &self.spec.ports {
-            args.push(port.to_string());
-            let from = match port.split_once(':') {
-                Some((first, _second)) => first,
-                None => port,
-            };
+        let mut args: Vec<String> = Vec::with_capacity(forwarded_ports.len() * 2 + 14);
+        let metrics_port = metrics_port(forwarded_ports);
+        match ordinal_services {
+            true => {
+                args.push("service".to_owned());
+                credential_args(credentials, &mut args);
+                ordinals::args(&mut args);
+                args.push("--metrics-address".to_owned());
+                args.push(format!("0.0.0.0:{metrics_port}"));
+            }
+            false => self.add_vector_args(credentials, metrics_port, &mut args),
+        }
+        for port in forwarded_ports {
+            if !ordinal_services {
+                args.push("--ports".to_owned());
+                args.push(format!("{}:{}", port.port, port.remote_port));
+            }
 
-            let int_port: i32 = from.parse().unwrap();
+            let int_port = i32::from(port.port);
+            let target_port = match pool {
+                Some((_, allocation)) => allocation
+                    .get(&(int_port as u16))
+                    .map_or(int_port, |listen| *listen as i32),
+                None => int_port,
+            };
             ports.push(ServicePort {
-                name: Some(port.to_string().replace(':', "-")),
+                name: Some(port.name.clone()),
                 port: int_port,
                 protocol: Some("TCP".to_owned()),
                 target_port: Some(
-                    k8s_openapi::apimachinery::pkg::util::intstr::IntOrString::Int(int_port),
+                    k8s_openapi::apimachinery::pkg::util::intstr::IntOrString::Int(target_port),
                 ),
-                app_protocol: None,
+                app_protocol: port.app_protocol.clone(),
                 ..Default::default()
             });
         }
@@ -285,13 +1304,52 @@ impl ForwardedService {
             "port-forward-operator.rs/forwardedservice".to_owned(),
             self.name_any(),
         );
+        let mut deployment_annotations = self.annotate();
+        let mut template_annotations = None;
+        if let Some(hash) = config_hash {
+            match self.spec.credential_reload.unwrap_or_default() {
+                CredentialReload::InPlace => {
+                    deployment_annotations.insert(ANNOTATION_KUBECONFIG_HASH.to_owned(), hash);
+                }
+                CredentialReload::Restart => {
+                    template_annotations = Some(std::collections::BTreeMap::from([(
+                        ANNOTATION_KUBECONFIG_HASH.to_owned(),
+                        hash,
+                    )]));
+                }
+            }
+        }
+        let (mut volume_mounts, mut volumes) =
+            credential_volumes(credentials.secret(), credentials.config_map());
+        if ordinal_services {
+            let (mount, volume) = ordinals::volume(self);
+            volume_mounts.push(mount);
+            volumes.push(volume);
+        }
+        let clusters = match (&self.spec.traffic_split, &self.spec.failover) {
+            (Some(_), _) => Some(split::forwarder(self, others)),
+            (None, Some(_)) => Some(failover::forwarder(self, others)),
+            (None, None) => None,
+        };
+        if let Some((cluster_args, mounts, added)) = clusters {
+            args.extend(cluster_args);
+            volume_mounts.extend(mounts);
+            volumes.extend(added);
+        }
+
         let api_resource = Self::api_resource();
+        let template = self.spec.service_template.clone().unwrap_or_default();
+        let mut service_labels = template.labels;
+        service_labels.extend(labels.clone());
+        let mut service_annotations = template.annotations;
+        service_annotations.extend(self.annotate());
+        let service_type = template.type_.unwrap_or_default();
         let new_service = Service {
             metadata: kube::core::ObjectMeta {
-                annotations: Some(self.annotate()),
+                annotations: Some(service_annotations),
                 finalizers: None,
-                labels: Some(labels.clone()),
-                name: Some(self.name_any()),
+                labels: Some(service_labels),
+                name: Some(self.service_name()),
                 namespace: self.namespace(),
                 owner_references: Some(vec![OwnerReference {
                     api_version: api_resource.api_version.clone(),
@@ -305,17 +1363,29 @@ impl ForwardedService {
             },
             spec: Some(k8s_openapi::api::core::v1::ServiceSpec {
                 ports: Some(ports),
-                selector: Some(labels.clone()),
+                selector: Some(match pool {
+                    Some((pool, _)) => std::collections::BTreeMap::from([(
+                        pool::LABEL_POOL.to_owned(),
+                        pool.clone(),
+                    )]),
+                    None => labels.clone(),
+                }),
                 session_affinity: None,
-                type_: Some("ClusterIP".to_owned()),
+                type_: Some(match service_type {
+                    ServiceType::Headless => "ClusterIP".to_owned(),
+                    service_type => format!("{service_type:?}"),
+                }),
+                cluster_ip: (service_type == ServiceType::Headless).then(|| "None".to_owned()),
+                load_balancer_source_ranges: template.load_balancer_source_ranges,
+                ip_family_policy: template.ip_family_policy.map(|p| format!("{p:?}")),
                 ..Default::default()
             }),
             ..Default::default()
         };
 
-        let new_deployment = Deployment {
+        let mut new_deployment = Deployment {
             metadata: kube::core::ObjectMeta {
-                annotations: Some(self.annotate()),
+                annotations: Some(deployment_annotations),
                 finalizers: None,
                 labels: Some(labels.clone()),
                 name: Some(self.name_any()),
@@ -331,6 +1401,7 @@ impl ForwardedService {
                 ..Default::default()
             },
             spec: Some(DeploymentSpec {
+                replicas: Some(self.replicas()),
                 revision_history_limit: Some(2),
                 selector: LabelSelector {
                     match_expressions: None,
@@ -338,6 +1409,7 @@ impl ForwardedService {
                 },
                 template: k8s_openapi::api::core::v1::PodTemplateSpec {
                     metadata: Some(ObjectMeta {
+                        annotations: template_annotations,
                         labels: Some(labels),
                         ..Default::default()
                     }),
@@ -348,25 +1420,21 @@ impl ForwardedService {
                             env: None,
                             image: Some(ctx.image.clone()),
                             name: "forwarder".to_owned(),
-                            ports: None,
-                            volume_mounts: Some(vec![VolumeMount {
-                                mount_path: KUBE_CONFIG_PATH.to_owned(),
-                                name: "kubeconfig".to_owned(),
-                                read_only: Some(true),
+                            ports: Some(vec![ContainerPort {
+                                container_port: metrics_port,
+                                name: Some("metrics".to_owned()),
+                                protocol: Some("TCP".to_owned()),
                                 ..Default::default()
                             }]),
+                            volume_mounts: Some(volume_mounts),
                             ..Default::default()
                         }],
                         restart_policy: None,
-                        volumes: Some(vec![Volume {
-                            name: "kubeconfig".to_owned(),
-                            secret: Some(SecretVolumeSource {
-                                optional: Some(false),
-                                secret_name: Some(self.spec.kube_config.secret.clone()),
-                                ..Default::default()
-                            }),
-                            ..Default::default()
-                        }]),
+                        service_account_name: match credentials {
+                            Credentials::InCluster => Some(rbac::service_account_name(self)),
+                            _ => None,
+                        },
+                        volumes: Some(volumes),
                         ..Default::default()
                     }),
                 },
@@ -374,6 +1442,18 @@ impl ForwardedService {
             }),
             ..Default::default()
         };
+        let template = self.spec.pod_template.clone().unwrap_or_default();
+        let spec = new_deployment.spec.as_mut().unwrap();
+        // the configured replicas, so suspending and resuming does not roll the pods
+        if self.spec.replicas.unwrap_or(1) > 1 {
+            disruption::spread(self, &mut spec.template);
+        }
+        pod::harden(
+            template.security.clone().unwrap_or_default(),
+            matches!(credentials, Credentials::InCluster),
+            &mut spec.template,
+        );
+        pod::merge(template, &mut spec.template);
         Ok((new_service, new_deployment))
     }
 
@@ -383,7 +1463,18 @@ impl ForwardedService {
             .read()
             .await
             .recorder(ctx.client.clone(), self);
-        // Document doesn't have any real cleanup, so we just publish an event
+        ctx.references.remove(self);
+        // clients of a referenced cluster stay with the cluster
+        if let (None, Ok(credentials)) =
+            (&self.spec.cluster_ref, Credentials::from_spec(self, None))
+        {
+            ctx.clients.forget(&self.namespace().unwrap(), &credentials);
+        }
+        // Roles in other namespaces cannot be owned by the document
+        rbac::revoke_except(ctx.client.clone(), self, None).await?;
+        if let Some(pool) = self.status.as_ref().and_then(|s| s.pool.as_ref()) {
+            pool::leave(ctx.as_ref(), self, pool).await?;
+        }
         recorder
             .publish(Event {
                 type_: EventType::Normal,
@@ -397,3 +1488,80 @@ impl ForwardedService {
         Ok(Action::await_change())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use hyper::{Body, Request, Response};
+    use k8s_openapi::api::core::v1::{Service, ServiceSpec};
+    use kube::{core::ObjectMeta, Api, Client};
+
+    use super::ANNOTATION_GENERATION;
+    use crate::crd::{ForwardedService, ForwardedServiceSpec};
+
+    fn service(generation: &str, ip_family_policy: Option<&str>) -> Service {
+        Service {
+            metadata: ObjectMeta {
+                name: Some("postgres".to_owned()),
+                namespace: Some("default".to_owned()),
+                annotations: Some(
+                    [(ANNOTATION_GENERATION.to_owned(), generation.to_owned())].into(),
+                ),
+                ..Default::default()
+            },
+            spec: Some(ServiceSpec {
+                ip_family_policy: ip_family_policy.map(str::to_owned),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_removes_opted_out_fields() {
+        // an API server holding a service that still prefers dual stack
+        let stored = Arc::new(Mutex::new(service("1", Some("PreferDualStack"))));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let server = {
+            let (stored, requests) = (stored.clone(), requests.clone());
+            tower::service_fn(move |request: Request<Body>| {
+                let (stored, requests) = (stored.clone(), requests.clone());
+                async move {
+                    let (parts, body) = request.into_parts();
+                    let body = hyper::body::to_bytes(body).await.unwrap();
+                    if parts.method == "PATCH" {
+                        // a lone field manager owns every field, so the applied object
+                        // replaces the stored one
+                        *stored.lock().unwrap() = serde_json::from_slice(&body).unwrap();
+                        requests.lock().unwrap().push((
+                            parts.uri.to_string(),
+                            parts.headers["content-type"].to_str().unwrap().to_owned(),
+                        ));
+                    }
+                    let stored = serde_json::to_vec(&*stored.lock().unwrap()).unwrap();
+                    Ok::<_, std::convert::Infallible>(Response::new(Body::from(stored)))
+                }
+            })
+        };
+        let services = Api::<Service>::namespaced(Client::new(server, "default"), "default");
+
+        let mut fs = ForwardedService::new("postgres", ForwardedServiceSpec::default());
+        fs.metadata.generation = Some(2);
+        let updated = fs
+            .create_or_update(
+                &services,
+                service("2", None),
+                ForwardedService::compare_generation,
+            )
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(1, requests.len());
+        assert!(requests[0].0.contains("fieldManager=port-forward-operator"));
+        assert!(requests[0].0.contains("force=true"));
+        assert_eq!("application/apply-patch+yaml", requests[0].1);
+        assert_eq!(None, updated.spec.unwrap().ip_family_policy);
+    }
+}
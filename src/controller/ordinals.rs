@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::{
+    api::core::v1::{
+        ConfigMap, ConfigMapVolumeSource, Service, ServicePort, ServiceSpec, Volume, VolumeMount,
+    },
+    apimachinery::pkg::util::intstr::IntOrString,
+};
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    core::ObjectMeta,
+    Api, Client, ResourceExt,
+};
+
+use super::{delete_if_exists, owner_reference};
+use crate::{
+    crd::{ForwardedPort, ForwardedService, Target, TargetKind},
+    error::Error,
+    service::{ForwardTarget, ForwarderConfig, PortMapping},
+};
+
+const LABEL_FORWARDED_SERVICE: &str = "port-forward-operator.rs/forwardedservice";
+const LABEL_ORDINAL: &str = "port-forward-operator.rs/ordinal";
+const TARGETS_CONFIG_PATH: &str = "/etc/port-forward-operator/targets";
+const CONFIG_KEY: &str = "config.json";
+/// Ordinal listeners are numbered from here on, clear of the metrics port
+const FIRST_LISTEN_PORT: u16 = 20000;
+
+/// Config map with the targets of a forwarder serving per-ordinal services
+pub(crate) fn config_map_name(fs: &ForwardedService) -> String {
+    format!("{}-targets", fs.name_any())
+}
+
+fn service_name(fs: &ForwardedService, ordinal: i32) -> String {
+    format!("{}-{ordinal}", fs.name_any())
+}
+
+/// Arguments pointing the forwarder at the generated targets
+pub(crate) fn args(args: &mut Vec<String>) {
+    args.push("--config".to_owned());
+    args.push(format!("{TARGETS_CONFIG_PATH}/{CONFIG_KEY}"));
+}
+
+/// Mount of the generated targets into the forwarder
+pub(crate) fn volume(fs: &ForwardedService) -> (VolumeMount, Volume) {
+    (
+        VolumeMount {
+            mount_path: TARGETS_CONFIG_PATH.to_owned(),
+            name: "targets".to_owned(),
+            read_only: Some(true),
+            ..Default::default()
+        },
+        Volume {
+            name: "targets".to_owned(),
+            config_map: Some(ConfigMapVolumeSource {
+                optional: Some(false),
+                name: Some(config_map_name(fs)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    )
+}
+
+/// Forwarder ports of every ordinal, one per forwarded port, skipping the local ports
+/// the aggregate service already listens on
+pub(crate) fn listen_ports(locals: &[u16], replicas: usize) -> Vec<Vec<u16>> {
+    let mut next = FIRST_LISTEN_PORT;
+    (0..replicas)
+        .map(|_| {
+            locals
+                .iter()
+                .map(|_| {
+                    while locals.contains(&next) {
+                        next += 1;
+                    }
+                    next += 1;
+                    next - 1
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Keeps a local service per ordinal of the stateful set target, plus the forwarder
+/// config routing each to its pod. Without `replicas` both are removed.
+pub(crate) async fn sync(
+    client: Client,
+    fs: &ForwardedService,
+    target_namespace: &str,
+    ports: &[ForwardedPort],
+    replicas: Option<i32>,
+) -> Result<(), Error> {
+    let ns = fs.namespace().unwrap();
+    let services: Api<Service> = Api::namespaced(client.clone(), &ns);
+    let config_maps: Api<ConfigMap> = Api::namespaced(client, &ns);
+    let params = PatchParams::apply("port-forward-operator").force();
+    let replicas = replicas.map(|r| r.max(0));
+    let target = fs.target();
+    let stateful_set = target.name.clone().unwrap_or_default();
+
+    match replicas {
+        Some(replicas) => {
+            let locals: Vec<u16> = ports.iter().map(|p| p.port).collect();
+            let listen = listen_ports(&locals, replicas as usize);
+            let mut config = ForwarderConfig {
+                targets: vec![ForwardTarget {
+                    namespace: target_namespace.to_owned(),
+                    target,
+                    ports: ports
+                        .iter()
+                        .map(|p| PortMapping {
+                            local: p.port,
+                            remote: p.remote_port,
+                        })
+                        .collect(),
+                }],
+            };
+            for (ordinal, listen) in listen.iter().enumerate() {
+                config.targets.push(ForwardTarget {
+                    namespace: target_namespace.to_owned(),
+                    target: Target {
+                        kind: TargetKind::Pod,
+                        name: Some(format!("{stateful_set}-{ordinal}")),
+                        ..Default::default()
+                    },
+                    ports: ports
+                        .iter()
+                        .zip(listen)
+                        .map(|(p, local)| PortMapping {
+                            local: *local,
+                            remote: p.remote_port,
+                        })
+                        .collect(),
+                });
+            }
+            let config_map = ConfigMap {
+                metadata: ObjectMeta {
+                    name: Some(config_map_name(fs)),
+                    namespace: Some(ns.clone()),
+                    owner_references: Some(vec![owner_reference(fs)]),
+                    ..Default::default()
+                },
+                data: Some(BTreeMap::from([(
+                    CONFIG_KEY.to_owned(),
+                    serde_json::to_string(&config).expect("serializable config"),
+                )])),
+                ..Default::default()
+            };
+            config_maps
+                .patch(&config_map_name(fs), &params, &Patch::Apply(&config_map))
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+
+            for (ordinal, listen) in listen.iter().enumerate() {
+                let name = service_name(fs, ordinal as i32);
+                let service = Service {
+                    metadata: ObjectMeta {
+                        name: Some(name.clone()),
+                        namespace: Some(ns.clone()),
+                        labels: Some(BTreeMap::from([
+                            (LABEL_FORWARDED_SERVICE.to_owned(), fs.name_any()),
+                            (LABEL_ORDINAL.to_owned(), ordinal.to_string()),
+                        ])),
+                        owner_references: Some(vec![owner_reference(fs)]),
+                        ..Default::default()
+                    },
+                    spec: Some(ServiceSpec {
+                        ports: Some(
+                            ports
+                                .iter()
+                                .zip(listen)
+                                .map(|(p, local)| ServicePort {
+                                    name: Some(p.name.clone()),
+                                    port: i32::from(p.port),
+                                    protocol: Some("TCP".to_owned()),
+                                    target_port: Some(IntOrString::Int(i32::from(*local))),
+                                    app_protocol: p.app_protocol.clone(),
+                                    ..Default::default()
+                                })
+                                .collect(),
+                        ),
+                        selector: Some(BTreeMap::from([(
+                            LABEL_FORWARDED_SERVICE.to_owned(),
+                            fs.name_any(),
+                        )])),
+                        type_: Some("ClusterIP".to_owned()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                };
+                services
+                    .patch(&name, &params, &Patch::Apply(&service))
+                    .await
+                    .map_err(|e| Error::Kubernetes { source: e })?;
+            }
+        }
+        None => delete_if_exists(config_maps, &config_map_name(fs)).await?,
+    }
+
+    // ordinals the stateful set was scaled down from
+    let owned = ListParams::default().labels(&format!(
+        "{LABEL_FORWARDED_SERVICE}={},{LABEL_ORDINAL}",
+        fs.name_any()
+    ));
+    for service in services
+        .list_metadata(&owned)
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?
+    {
+        let ordinal: Option<i32> = service
+            .labels()
+            .get(LABEL_ORDINAL)
+            .and_then(|o| o.parse().ok());
+        if ordinal.is_none_or(|o| replicas.is_none_or(|r| o >= r)) {
+            delete_if_exists(services.clone(), &service.name_any()).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::listen_ports;
+
+    #[test]
+    fn test_listen_ports_skip_local_ports() {
+        assert_eq!(
+            vec![vec![20000, 20002], vec![20003, 20004]],
+            listen_ports(&[9092, 20001], 2)
+        );
+    }
+}
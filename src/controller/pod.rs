@@ -0,0 +1,347 @@
+use k8s_openapi::api::core::v1::{
+    Affinity, Capabilities, PodAffinity, PodAntiAffinity, PodSecurityContext, PodTemplateSpec,
+    SeccompProfile,
+};
+
+use crate::crd::{PodSecurity, PodTemplate};
+
+/// Unprivileged user the forwarder runs as, since the image does not set one
+const NON_ROOT_USER: i64 = 65534;
+
+/// Applies the security defaults to the generated forwarder pod, except those turned
+/// off. Only the restricted fields are set, anything else in the security contexts is
+/// kept. The service account token is only needed for in-cluster credentials.
+pub(crate) fn harden(security: PodSecurity, in_cluster: bool, pod: &mut PodTemplateSpec) {
+    let enabled = |setting: Option<bool>| setting.unwrap_or(true);
+    let spec = pod.spec.get_or_insert_with(Default::default);
+    if enabled(security.disable_service_account_token) && !in_cluster {
+        spec.automount_service_account_token = Some(false);
+    }
+    if enabled(security.seccomp_runtime_default) {
+        spec.security_context
+            .get_or_insert_with(PodSecurityContext::default)
+            .seccomp_profile = Some(SeccompProfile {
+            type_: "RuntimeDefault".to_owned(),
+            ..Default::default()
+        });
+    }
+    for container in spec.containers.iter_mut() {
+        let context = container
+            .security_context
+            .get_or_insert_with(Default::default);
+        if enabled(security.disallow_privilege_escalation) {
+            context.allow_privilege_escalation = Some(false);
+        }
+        if enabled(security.run_as_non_root) {
+            context.run_as_non_root = Some(true);
+            context.run_as_user = Some(NON_ROOT_USER);
+            context.run_as_group = Some(NON_ROOT_USER);
+        }
+        if enabled(security.read_only_root_filesystem) {
+            context.read_only_root_filesystem = Some(true);
+        }
+        if enabled(security.drop_all_capabilities) {
+            context
+                .capabilities
+                .get_or_insert_with(Capabilities::default)
+                .drop = Some(vec!["ALL".to_owned()]);
+        }
+    }
+}
+
+/// Merges the overrides of `template` onto the generated forwarder pod. Maps and lists
+/// are merged by key, keeping what the controller set; the container arguments and
+/// volumes are never touched.
+pub(crate) fn merge(template: PodTemplate, pod: &mut PodTemplateSpec) {
+    let metadata = pod.metadata.get_or_insert_with(Default::default);
+    for (field, overrides) in [
+        (&mut metadata.labels, template.labels),
+        (&mut metadata.annotations, template.annotations),
+    ] {
+        if overrides.is_empty() {
+            continue;
+        }
+        let generated = field.take().unwrap_or_default();
+        let mut merged = overrides;
+        merged.extend(generated);
+        *field = Some(merged);
+    }
+
+    let spec = pod.spec.get_or_insert_with(Default::default);
+    if let Some(container) = spec.containers.first_mut() {
+        if template.resources.is_some() {
+            container.resources = template.resources;
+        }
+        if !template.env.is_empty() {
+            let env = container.env.get_or_insert_with(Vec::new);
+            for var in template.env {
+                if !env.iter().any(|set| set.name == var.name) {
+                    env.push(var);
+                }
+            }
+        }
+    }
+    if !template.node_selector.is_empty() {
+        spec.node_selector
+            .get_or_insert_with(Default::default)
+            .extend(template.node_selector);
+    }
+    if !template.tolerations.is_empty() {
+        spec.tolerations
+            .get_or_insert_with(Vec::new)
+            .extend(template.tolerations);
+    }
+    if !template.image_pull_secrets.is_empty() {
+        let secrets = spec.image_pull_secrets.get_or_insert_with(Vec::new);
+        for secret in template.image_pull_secrets {
+            if !secrets.iter().any(|set| set.name == secret.name) {
+                secrets.push(secret);
+            }
+        }
+    }
+    if let Some(affinity) = template.affinity {
+        spec.affinity = Some(match spec.affinity.take() {
+            Some(generated) => combine(generated, affinity),
+            None => affinity,
+        });
+    }
+    if template.priority_class_name.is_some() {
+        spec.priority_class_name = template.priority_class_name;
+    }
+    if spec.service_account_name.is_none() {
+        spec.service_account_name = template.service_account_name;
+    }
+}
+
+fn concat<T>(generated: Option<Vec<T>>, overrides: Option<Vec<T>>) -> Option<Vec<T>> {
+    match (generated, overrides) {
+        (Some(mut generated), Some(overrides)) => {
+            generated.extend(overrides);
+            Some(generated)
+        }
+        (generated, overrides) => overrides.or(generated),
+    }
+}
+
+/// Adds the pod affinity terms of `overrides` to the generated spreading terms, which
+/// all have to hold. Node selector terms are alternatives, so a node affinity of
+/// `overrides` replaces the generated one instead.
+fn combine(generated: Affinity, overrides: Affinity) -> Affinity {
+    let pod_affinity = match (generated.pod_affinity, overrides.pod_affinity) {
+        (Some(generated), Some(overrides)) => Some(PodAffinity {
+            required_during_scheduling_ignored_during_execution: concat(
+                generated.required_during_scheduling_ignored_during_execution,
+                overrides.required_during_scheduling_ignored_during_execution,
+            ),
+            preferred_during_scheduling_ignored_during_execution: concat(
+                generated.preferred_during_scheduling_ignored_during_execution,
+                overrides.preferred_during_scheduling_ignored_during_execution,
+            ),
+        }),
+        (generated, overrides) => overrides.or(generated),
+    };
+    let pod_anti_affinity = match (generated.pod_anti_affinity, overrides.pod_anti_affinity) {
+        (Some(generated), Some(overrides)) => Some(PodAntiAffinity {
+            required_during_scheduling_ignored_during_execution: concat(
+                generated.required_during_scheduling_ignored_during_execution,
+                overrides.required_during_scheduling_ignored_during_execution,
+            ),
+            preferred_during_scheduling_ignored_during_execution: concat(
+                generated.preferred_during_scheduling_ignored_during_execution,
+                overrides.preferred_during_scheduling_ignored_during_execution,
+            ),
+        }),
+        (generated, overrides) => overrides.or(generated),
+    };
+    Affinity {
+        node_affinity: overrides.node_affinity.or(generated.node_affinity),
+        pod_affinity,
+        pod_anti_affinity,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::core::v1::{
+        Affinity, Container, EnvVar, NodeAffinity, PodAffinityTerm, PodAntiAffinity,
+        PodSecurityContext, PodSpec, PodTemplateSpec, WeightedPodAffinityTerm,
+    };
+    use kube::core::ObjectMeta;
+
+    use super::{harden, merge};
+    use crate::crd::{PodSecurity, PodTemplate};
+
+    fn env(name: &str, value: &str) -> EnvVar {
+        EnvVar {
+            name: name.to_owned(),
+            value: Some(value.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_keeps_generated_fields() {
+        let mut pod = PodTemplateSpec {
+            metadata: Some(ObjectMeta {
+                labels: Some(BTreeMap::from([("app".to_owned(), "fwd".to_owned())])),
+                ..Default::default()
+            }),
+            spec: Some(PodSpec {
+                containers: vec![Container {
+                    args: Some(vec!["service".to_owned()]),
+                    env: Some(vec![env("RUST_LOG", "info")]),
+                    ..Default::default()
+                }],
+                service_account_name: Some("in-cluster".to_owned()),
+                ..Default::default()
+            }),
+        };
+        merge(
+            PodTemplate {
+                labels: BTreeMap::from([
+                    ("app".to_owned(), "other".to_owned()),
+                    ("team".to_owned(), "db".to_owned()),
+                ]),
+                env: vec![env("RUST_LOG", "debug"), env("HTTPS_PROXY", "proxy:3128")],
+                priority_class_name: Some("critical".to_owned()),
+                service_account_name: Some("custom".to_owned()),
+                ..Default::default()
+            },
+            &mut pod,
+        );
+        let labels = pod.metadata.unwrap().labels.unwrap();
+        assert_eq!("fwd", labels["app"]);
+        assert_eq!("db", labels["team"]);
+        let spec = pod.spec.unwrap();
+        assert_eq!(Some(vec!["service".to_owned()]), spec.containers[0].args);
+        assert_eq!(
+            Some(vec![
+                env("RUST_LOG", "info"),
+                env("HTTPS_PROXY", "proxy:3128")
+            ]),
+            spec.containers[0].env
+        );
+        assert_eq!(Some("critical".to_owned()), spec.priority_class_name);
+        assert_eq!(Some("in-cluster".to_owned()), spec.service_account_name);
+    }
+
+    #[test]
+    fn test_harden_honours_opt_outs() {
+        let pod = || PodTemplateSpec {
+            spec: Some(PodSpec {
+                containers: vec![Container::default()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut hardened = pod();
+        harden(PodSecurity::default(), false, &mut hardened);
+        let spec = hardened.spec.unwrap();
+        assert_eq!(Some(false), spec.automount_service_account_token);
+        let context = spec.containers[0].security_context.clone().unwrap();
+        assert_eq!(Some(true), context.run_as_non_root);
+        assert_eq!(Some(true), context.read_only_root_filesystem);
+        assert_eq!(Some(false), context.allow_privilege_escalation);
+        assert!(spec.security_context.is_some());
+
+        let mut relaxed = pod();
+        harden(
+            PodSecurity {
+                read_only_root_filesystem: Some(false),
+                seccomp_runtime_default: Some(false),
+                disallow_privilege_escalation: Some(false),
+                ..Default::default()
+            },
+            true,
+            &mut relaxed,
+        );
+        let spec = relaxed.spec.unwrap();
+        assert_eq!(None, spec.automount_service_account_token);
+        assert_eq!(None, spec.security_context);
+        let context = spec.containers[0].security_context.clone().unwrap();
+        assert_eq!(Some(true), context.run_as_non_root);
+        assert_eq!(None, context.read_only_root_filesystem);
+        assert_eq!(None, context.allow_privilege_escalation);
+    }
+
+    #[test]
+    fn test_harden_keeps_other_security_settings() {
+        let mut pod = PodTemplateSpec {
+            spec: Some(PodSpec {
+                containers: vec![Container::default()],
+                security_context: Some(PodSecurityContext {
+                    fs_group: Some(2000),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        harden(PodSecurity::default(), false, &mut pod);
+        let context = pod.spec.unwrap().security_context.unwrap();
+        assert_eq!(Some(2000), context.fs_group);
+        assert_eq!(
+            "RuntimeDefault",
+            context.seccomp_profile.unwrap().type_.as_str()
+        );
+    }
+
+    #[test]
+    fn test_merge_adds_affinity_to_spreading() {
+        let term = |key: &str| WeightedPodAffinityTerm {
+            pod_affinity_term: PodAffinityTerm {
+                topology_key: key.to_owned(),
+                ..Default::default()
+            },
+            weight: 100,
+        };
+        let mut pod = PodTemplateSpec {
+            spec: Some(PodSpec {
+                containers: vec![Container::default()],
+                affinity: Some(Affinity {
+                    pod_anti_affinity: Some(PodAntiAffinity {
+                        preferred_during_scheduling_ignored_during_execution: Some(vec![term(
+                            "kubernetes.io/hostname",
+                        )]),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        merge(
+            PodTemplate {
+                affinity: Some(Affinity {
+                    node_affinity: Some(NodeAffinity::default()),
+                    pod_anti_affinity: Some(PodAntiAffinity {
+                        preferred_during_scheduling_ignored_during_execution: Some(vec![term(
+                            "topology.kubernetes.io/zone",
+                        )]),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            &mut pod,
+        );
+        let affinity = pod.spec.unwrap().affinity.unwrap();
+        assert!(affinity.node_affinity.is_some());
+        let keys: Vec<String> = affinity
+            .pod_anti_affinity
+            .unwrap()
+            .preferred_during_scheduling_ignored_during_execution
+            .unwrap()
+            .into_iter()
+            .map(|term| term.pod_affinity_term.topology_key)
+            .collect();
+        assert_eq!(
+            vec!["kubernetes.io/hostname", "topology.kubernetes.io/zone"],
+            keys
+        );
+    }
+}
@@ -0,0 +1,522 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::{
+    api::{
+        apps::v1::{Deployment, DeploymentSpec},
+        core::v1::{
+            ConfigMap, ConfigMapVolumeSource, Container, ContainerPort, PodSpec, PodTemplateSpec,
+            Volume, VolumeMount,
+        },
+    },
+    apimachinery::pkg::apis::meta::v1::{LabelSelector, OwnerReference},
+};
+use kube::{
+    api::{DeleteParams, Patch, PatchParams, PostParams, Preconditions},
+    core::ObjectMeta,
+    Api, ResourceExt,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    credential_args, credential_volumes, credentials::Credentials, owner_reference, pod, Context,
+    FORWARDER_METRICS_PORT,
+};
+use crate::{
+    crd::{
+        CredentialReload, ForwardedPort, ForwardedService, PodSecurity, Target,
+        ANNOTATION_KUBECONFIG_HASH,
+    },
+    error::Error,
+    service::{ForwardTarget, ForwarderConfig, PortMapping},
+};
+
+pub(crate) const LABEL_POOL: &str = "port-forward-operator.rs/pool";
+const POOL_CONFIG_PATH: &str = "/etc/port-forward-operator/pool";
+const CONFIG_KEY: &str = "config.json";
+const ALLOCATIONS_KEY: &str = "allocations.json";
+const MEMBERS_KEY: &str = "members.json";
+/// Pooled forwarders listen from here on, clear of the metrics port
+const FIRST_LISTEN_PORT: u16 = 20000;
+/// Members reconciled at the same time retry their conditional writes this often
+const WRITE_ATTEMPTS: usize = 5;
+
+/// Ports the pool forwarder listens on for each local port of a member, keyed by the
+/// local port
+pub(crate) type Allocation = BTreeMap<u16, u16>;
+
+/// Name of the pool serving forwards with these credentials. Forwards within the local
+/// cluster use a service account per `ForwardedService` and are not pooled.
+pub(crate) fn pool_name(credentials: &Credentials) -> Option<String> {
+    if let Credentials::InCluster = credentials {
+        return None;
+    }
+    let mut args = Vec::new();
+    credential_args(credentials, &mut args);
+    let key = format!(
+        "{}|{}|{}",
+        credentials.secret().unwrap_or_default(),
+        credentials.config_map().unwrap_or_default(),
+        args.join(" ")
+    );
+    Some(format!(
+        "forwarder-pool-{}",
+        &super::credentials::hash(key.as_bytes())[..10]
+    ))
+}
+
+fn allocation_key(fs: &str, local: u16) -> String {
+    format!("{fs}/{local}")
+}
+
+/// Assigns a listen port to every `fs/local` key. Earlier assignments are kept, so
+/// the services of other members do not change when one joins or leaves.
+pub(crate) fn allocate(previous: &BTreeMap<String, u16>, keys: &[String]) -> BTreeMap<String, u16> {
+    let mut allocations: BTreeMap<String, u16> = previous
+        .iter()
+        .filter(|(key, _)| keys.contains(key))
+        .map(|(key, port)| (key.clone(), *port))
+        .collect();
+    let mut next = FIRST_LISTEN_PORT;
+    for key in keys {
+        if allocations.contains_key(key) {
+            continue;
+        }
+        while allocations.values().any(|port| *port == next) {
+            next += 1;
+        }
+        allocations.insert(key.clone(), next);
+    }
+    allocations
+}
+
+/// A pooled `ForwardedService` as recorded in the config map of its pool
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct Member {
+    namespace: String,
+    #[serde(flatten)]
+    target: Target,
+    ports: Vec<PortMapping>,
+    /// Suspended or outside its schedule. The listen ports stay allocated, so resuming
+    /// keeps the service as is.
+    paused: bool,
+    credential_reload: CredentialReload,
+}
+
+/// Members and listen ports of a pool. Each member records only itself, so a reconcile
+/// neither lists the other forwards nor rewrites other pools.
+#[derive(Default, Debug)]
+struct Pool {
+    members: BTreeMap<String, Member>,
+    allocations: BTreeMap<String, u16>,
+}
+
+impl Pool {
+    fn read(config_map: &ConfigMap) -> Self {
+        let data = config_map.data.as_ref();
+        Self {
+            members: data
+                .and_then(|data| serde_json::from_str(data.get(MEMBERS_KEY)?).ok())
+                .unwrap_or_default(),
+            allocations: data
+                .and_then(|data| serde_json::from_str(data.get(ALLOCATIONS_KEY)?).ok())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Adds or updates member `name`, or removes it when `member` is `None`. The listen
+    /// ports of the other members are kept.
+    fn set(&mut self, name: &str, member: Option<Member>) {
+        match member {
+            Some(member) => self.members.insert(name.to_owned(), member),
+            None => self.members.remove(name),
+        };
+        let keys: Vec<String> = self
+            .members
+            .iter()
+            .flat_map(|(name, member)| {
+                member
+                    .ports
+                    .iter()
+                    .map(|port| allocation_key(name, port.local))
+            })
+            .collect();
+        self.allocations = allocate(&self.allocations, &keys);
+    }
+
+    fn allocation(&self, name: &str) -> Allocation {
+        self.members
+            .get(name)
+            .into_iter()
+            .flat_map(|member| &member.ports)
+            .map(|port| {
+                (
+                    port.local,
+                    self.allocations[&allocation_key(name, port.local)],
+                )
+            })
+            .collect()
+    }
+
+    fn config(&self) -> ForwarderConfig {
+        let targets = self
+            .members
+            .iter()
+            .filter(|(_, member)| !member.paused)
+            .map(|(name, member)| ForwardTarget {
+                namespace: member.namespace.clone(),
+                target: member.target.clone(),
+                ports: member
+                    .ports
+                    .iter()
+                    .map(|port| PortMapping {
+                        local: self.allocations[&allocation_key(name, port.local)],
+                        remote: port.remote,
+                    })
+                    .collect(),
+            })
+            .collect();
+        ForwarderConfig { targets }
+    }
+
+    fn data(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            (
+                CONFIG_KEY.to_owned(),
+                serde_json::to_string(&self.config()).expect("serializable config"),
+            ),
+            (
+                ALLOCATIONS_KEY.to_owned(),
+                serde_json::to_string(&self.allocations).expect("serializable allocations"),
+            ),
+            (
+                MEMBERS_KEY.to_owned(),
+                serde_json::to_string(&self.members).expect("serializable members"),
+            ),
+        ])
+    }
+
+    /// Whether a member asked for the forwarder to be rolled on rotated credentials
+    fn restarts(&self) -> bool {
+        self.members
+            .values()
+            .any(|member| member.credential_reload == CredentialReload::Restart)
+    }
+
+    /// Whether no member is forwarded, so the forwarder can be scaled down
+    fn is_idle(&self) -> bool {
+        self.members.values().all(|member| member.paused)
+    }
+}
+
+/// Records `member` for `fs` in the config map of `pool`, or drops `fs` from it with
+/// `None`, and removes the config map once the last member left. Writes are
+/// conditional on the version read, so members reconciled at the same time do not drop
+/// each other's ports. Returns the written config map, unless it was removed.
+async fn update(
+    config_maps: &Api<ConfigMap>,
+    pool: &str,
+    fs: &ForwardedService,
+    member: Option<Member>,
+) -> Result<Option<(ConfigMap, Pool)>, Error> {
+    let uid = fs.uid();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let stored = config_maps
+            .get_opt(pool)
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?;
+        let mut state = stored.as_ref().map(Pool::read).unwrap_or_default();
+        state.set(&fs.name_any(), member.clone());
+        let resource_version = stored.as_ref().and_then(|s| s.resource_version());
+
+        if state.members.is_empty() {
+            if stored.is_none() {
+                return Ok(None);
+            }
+            // the forwarder is owned by the config map and garbage collected with it
+            let params = DeleteParams {
+                preconditions: Some(Preconditions {
+                    resource_version,
+                    uid: None,
+                }),
+                ..Default::default()
+            };
+            match config_maps.delete(pool, &params).await {
+                Ok(_) => return Ok(None),
+                Err(kube::Error::Api(r)) if r.code == 404 => return Ok(None),
+                Err(kube::Error::Api(r)) if r.code == 409 && attempt < WRITE_ATTEMPTS => continue,
+                Err(e) => return Err(Error::Kubernetes { source: e }),
+            }
+        }
+
+        let mut owners: Vec<OwnerReference> = stored
+            .as_ref()
+            .and_then(|s| s.metadata.owner_references.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|owner| Some(&owner.uid) != uid.as_ref())
+            .collect();
+        if member.is_some() {
+            owners.push(owner_reference(fs));
+        }
+        let config_map = ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(pool.to_owned()),
+                namespace: fs.namespace(),
+                labels: Some(BTreeMap::from([(LABEL_POOL.to_owned(), pool.to_owned())])),
+                owner_references: Some(owners),
+                resource_version,
+                ..Default::default()
+            },
+            data: Some(state.data()),
+            ..Default::default()
+        };
+        let params = PostParams {
+            field_manager: Some("port-forward-operator".to_owned()),
+            ..Default::default()
+        };
+        let written = match stored {
+            Some(_) => config_maps.replace(pool, &params, &config_map).await,
+            None => config_maps.create(&params, &config_map).await,
+        };
+        match written {
+            Ok(written) => return Ok(Some((written, state))),
+            Err(kube::Error::Api(r)) if r.code == 409 && attempt < WRITE_ATTEMPTS => continue,
+            Err(e) => return Err(Error::Kubernetes { source: e }),
+        }
+    }
+}
+
+/// Joins `fs` to `pool` and applies the pool forwarder with the credentials of `fs`,
+/// which every member shares. Only this pool is rewritten. Returns the listen ports of
+/// `fs`.
+pub(crate) async fn join(
+    ctx: &Context,
+    fs: &ForwardedService,
+    pool: &str,
+    credentials: &Credentials<'_>,
+    config_hash: Option<String>,
+    forwarded_ports: &[ForwardedPort],
+    paused: bool,
+) -> Result<Allocation, Error> {
+    let ns = fs.namespace().unwrap();
+    let config_maps: Api<ConfigMap> = Api::namespaced(ctx.client.clone(), &ns);
+    let member = Member {
+        namespace: fs.spec.namespace.clone().unwrap_or_else(|| ns.clone()),
+        target: fs.target(),
+        ports: forwarded_ports
+            .iter()
+            .map(|p| PortMapping {
+                local: p.port,
+                remote: p.remote_port,
+            })
+            .collect(),
+        paused,
+        credential_reload: fs.spec.credential_reload.unwrap_or_default(),
+    };
+    let (config_map, state) = update(&config_maps, pool, fs, Some(member))
+        .await?
+        .expect("a pool with a member");
+
+    let mut args = vec!["service".to_owned()];
+    credential_args(credentials, &mut args);
+    args.push("--config".to_owned());
+    args.push(format!("{POOL_CONFIG_PATH}/{CONFIG_KEY}"));
+    args.push("--metrics-address".to_owned());
+    args.push(format!("0.0.0.0:{FORWARDER_METRICS_PORT}"));
+    let (mut volume_mounts, mut volumes) =
+        credential_volumes(credentials.secret(), credentials.config_map());
+    volume_mounts.push(VolumeMount {
+        mount_path: POOL_CONFIG_PATH.to_owned(),
+        name: "pool".to_owned(),
+        read_only: Some(true),
+        ..Default::default()
+    });
+    volumes.push(Volume {
+        name: "pool".to_owned(),
+        config_map: Some(ConfigMapVolumeSource {
+            optional: Some(false),
+            name: Some(pool.to_owned()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    // rotated credentials roll the pool when any member asks for it
+    let mut annotations = BTreeMap::new();
+    let mut template_annotations = None;
+    if let Some(hash) = config_hash {
+        match state.restarts() {
+            true => {
+                template_annotations = Some(BTreeMap::from([(
+                    ANNOTATION_KUBECONFIG_HASH.to_owned(),
+                    hash,
+                )]))
+            }
+            false => {
+                annotations.insert(ANNOTATION_KUBECONFIG_HASH.to_owned(), hash);
+            }
+        }
+    }
+    let labels = BTreeMap::from([(LABEL_POOL.to_owned(), pool.to_owned())]);
+    let mut deployment = Deployment {
+        metadata: ObjectMeta {
+            name: Some(pool.to_owned()),
+            namespace: Some(ns.clone()),
+            labels: Some(labels.clone()),
+            annotations: Some(annotations),
+            owner_references: Some(vec![owner_reference(&config_map)]),
+            ..Default::default()
+        },
+        spec: Some(DeploymentSpec {
+            replicas: Some(match state.is_idle() {
+                true => 0,
+                false => 1,
+            }),
+            revision_history_limit: Some(2),
+            selector: LabelSelector {
+                match_expressions: None,
+                match_labels: Some(labels.clone()),
+            },
+            template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    annotations: template_annotations,
+                    labels: Some(labels),
+                    ..Default::default()
+                }),
+                spec: Some(PodSpec {
+                    containers: vec![Container {
+                        args: Some(args),
+                        image: Some(ctx.image.clone()),
+                        name: "forwarder".to_owned(),
+                        ports: Some(vec![ContainerPort {
+                            container_port: FORWARDER_METRICS_PORT,
+                            name: Some("metrics".to_owned()),
+                            protocol: Some("TCP".to_owned()),
+                            ..Default::default()
+                        }]),
+                        volume_mounts: Some(volume_mounts),
+                        ..Default::default()
+                    }],
+                    volumes: Some(volumes),
+                    ..Default::default()
+                }),
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    if let Some(spec) = deployment.spec.as_mut() {
+        // pooled forwards are never in-cluster and share the security defaults
+        pod::harden(PodSecurity::default(), false, &mut spec.template);
+    }
+    Api::<Deployment>::namespaced(ctx.client.clone(), &ns)
+        .patch(
+            pool,
+            &PatchParams::apply("port-forward-operator").force(),
+            &Patch::Apply(&deployment),
+        )
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+    Ok(state.allocation(&fs.name_any()))
+}
+
+/// Drops `fs` from `pool`. The pool and its forwarder are removed with the last member,
+/// and the forwarder is scaled down when only paused members are left.
+pub(crate) async fn leave(ctx: &Context, fs: &ForwardedService, pool: &str) -> Result<(), Error> {
+    let ns = fs.namespace().unwrap();
+    let config_maps: Api<ConfigMap> = Api::namespaced(ctx.client.clone(), &ns);
+    match update(&config_maps, pool, fs, None).await? {
+        Some((_, state)) if state.is_idle() => {
+            match Api::<Deployment>::namespaced(ctx.client.clone(), &ns)
+                .patch(
+                    pool,
+                    &PatchParams::default(),
+                    &Patch::Merge(serde_json::json!({ "spec": { "replicas": 0 } })),
+                )
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
+                Err(e) => Err(Error::Kubernetes { source: e }),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{allocate, Member, Pool};
+    use crate::{
+        crd::{CredentialReload, Target},
+        service::PortMapping,
+    };
+
+    fn member(service: &str, local: u16, paused: bool) -> Member {
+        Member {
+            namespace: "db".to_owned(),
+            target: Target {
+                name: Some(service.to_owned()),
+                ..Default::default()
+            },
+            ports: vec![PortMapping {
+                local,
+                remote: local,
+            }],
+            paused,
+            credential_reload: CredentialReload::InPlace,
+        }
+    }
+
+    #[test]
+    fn test_allocate_keeps_previous_ports() {
+        let previous = BTreeMap::from([
+            ("postgres/5432".to_owned(), 20000),
+            ("redis/6379".to_owned(), 20001),
+            ("gone/80".to_owned(), 20002),
+        ]);
+        let allocations = allocate(
+            &previous,
+            &[
+                "kafka/9092".to_owned(),
+                "redis/6379".to_owned(),
+                "postgres/5432".to_owned(),
+                "nats/4222".to_owned(),
+            ],
+        );
+        assert_eq!(
+            BTreeMap::from([
+                ("kafka/9092".to_owned(), 20002),
+                ("nats/4222".to_owned(), 20003),
+                ("postgres/5432".to_owned(), 20000),
+                ("redis/6379".to_owned(), 20001),
+            ]),
+            allocations
+        );
+    }
+
+    #[test]
+    fn test_members_only_change_their_own_entry() {
+        let mut pool = Pool::default();
+        pool.set("postgres", Some(member("postgres", 5432, false)));
+        pool.set("redis", Some(member("redis", 6379, false)));
+        pool.set("kafka", Some(member("kafka", 9092, true)));
+        pool.set("postgres", None);
+        assert_eq!(BTreeMap::from([(6379, 20001)]), pool.allocation("redis"));
+        assert_eq!(BTreeMap::from([(9092, 20002)]), pool.allocation("kafka"));
+        assert!(!pool.restarts());
+        assert!(!pool.is_idle());
+
+        // paused members keep their ports but are not served
+        let config = pool.config();
+        assert_eq!(1, config.targets.len());
+        assert_eq!(Some("redis"), config.targets[0].target.name.as_deref());
+        assert_eq!(20001, config.targets[0].ports[0].local);
+
+        pool.set("redis", None);
+        assert!(pool.is_idle());
+    }
+}
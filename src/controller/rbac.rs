@@ -0,0 +1,261 @@
+use k8s_openapi::{
+    api::{
+        core::v1::{Namespace, ServiceAccount},
+        rbac::v1::{PolicyRule, Role, RoleBinding, RoleRef, Subject},
+    },
+    apimachinery::pkg::apis::meta::v1::OwnerReference,
+};
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    core::{CustomResourceExt, ObjectMeta},
+    Api, Client, Resource, ResourceExt,
+};
+use std::collections::BTreeMap;
+
+use super::delete_if_exists;
+use crate::{crd::ForwardedService, error::Error};
+
+const LABEL_NAMESPACE: &str = "port-forward-operator.rs/namespace";
+const LABEL_NAME: &str = "port-forward-operator.rs/forwardedservice";
+/// Namespace annotation listing the namespaces, comma separated or `*` for every one,
+/// whose forwards within the local cluster may reach into the annotated namespace
+pub(crate) const ANNOTATION_FORWARD_FROM: &str = "port-forward-operator.rs/forward-from";
+
+/// Whether namespace `target` opted in to forwards from namespace `from`
+pub(crate) fn allows(target: &Namespace, from: &str) -> bool {
+    target.name_any() == from
+        || target
+            .annotations()
+            .get(ANNOTATION_FORWARD_FROM)
+            .is_some_and(|allowed| {
+                allowed
+                    .split(',')
+                    .map(str::trim)
+                    .any(|allowed| allowed == "*" || allowed == from)
+            })
+}
+
+/// Checks that the local namespace `target` allows forwards from namespace `from`.
+/// Returns the message for a `Forbidden` reason when it does not.
+pub(crate) async fn opted_in(
+    client: Client,
+    from: &str,
+    target: &str,
+) -> Result<Result<(), String>, Error> {
+    if from == target {
+        return Ok(Ok(()));
+    }
+    let namespace = Api::<Namespace>::all(client)
+        .get_opt(target)
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+    Ok(match namespace {
+        Some(namespace) if allows(&namespace, from) => Ok(()),
+        _ => Err(format!(
+            "namespace `{target}` does not list `{from}` in its `{ANNOTATION_FORWARD_FROM}` annotation"
+        )),
+    })
+}
+
+/// Service account the forwarder runs as when it uses the local cluster
+pub(crate) fn service_account_name(fs: &ForwardedService) -> String {
+    format!("{}-forwarder", fs.name_any())
+}
+
+/// How the API server authenticates a service account, for access reviews on its behalf
+pub(crate) struct ServiceAccountUser {
+    pub user: String,
+    pub groups: Vec<String>,
+}
+
+impl ServiceAccountUser {
+    pub fn new(namespace: &str, name: &str) -> Self {
+        Self {
+            user: format!("system:serviceaccount:{namespace}:{name}"),
+            groups: vec![
+                "system:serviceaccounts".to_owned(),
+                format!("system:serviceaccounts:{namespace}"),
+                "system:authenticated".to_owned(),
+            ],
+        }
+    }
+}
+
+/// Role and binding name in the target namespace, which may differ from the namespace
+/// of the forwarded service
+fn role_name(fs: &ForwardedService) -> String {
+    format!(
+        "port-forward-operator-{}-{}",
+        fs.namespace().unwrap(),
+        fs.name_any()
+    )
+}
+
+fn labels(fs: &ForwardedService) -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (LABEL_NAMESPACE.to_owned(), fs.namespace().unwrap()),
+        (LABEL_NAME.to_owned(), fs.name_any()),
+    ])
+}
+
+fn selector(fs: &ForwardedService) -> ListParams {
+    let selector = labels(fs)
+        .into_iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    ListParams::default().labels(&selector)
+}
+
+fn rules() -> Vec<PolicyRule> {
+    let rule = |group: &str, resources: &[&str], verbs: &[&str]| PolicyRule {
+        api_groups: Some(vec![group.to_owned()]),
+        resources: Some(resources.iter().map(|r| r.to_string()).collect()),
+        verbs: verbs.iter().map(|v| v.to_string()).collect(),
+        ..Default::default()
+    };
+    vec![
+        rule("", &["services"], &["get"]),
+        // `Leader` targets watch for the labels to move
+        rule("", &["pods"], &["get", "list", "watch"]),
+        rule("", &["pods/portforward"], &["create"]),
+        // resolving deployment and stateful set targets to pods
+        rule("apps", &["deployments", "statefulsets"], &["get"]),
+    ]
+}
+
+/// Creates the forwarder service account and grants it `pods/portforward` in the target
+/// namespace only, which needs to have opted in through `opted_in` first
+pub(crate) async fn grant(
+    client: Client,
+    fs: &ForwardedService,
+    target: &str,
+) -> Result<(), Error> {
+    let ns = fs.namespace().unwrap();
+    let params = PatchParams::apply("port-forward-operator").force();
+    let api_resource = ForwardedService::api_resource();
+    let service_account = ServiceAccount {
+        metadata: ObjectMeta {
+            name: Some(service_account_name(fs)),
+            namespace: Some(ns.clone()),
+            labels: Some(labels(fs)),
+            owner_references: Some(vec![OwnerReference {
+                api_version: api_resource.api_version,
+                controller: Some(false),
+                kind: api_resource.kind,
+                name: fs.name_any(),
+                uid: fs.meta().uid.clone().unwrap(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let service_accounts: Api<ServiceAccount> = Api::namespaced(client.clone(), &ns);
+    service_accounts
+        .patch(
+            &service_account.name_any(),
+            &params,
+            &Patch::Apply(&service_account),
+        )
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+
+    let metadata = ObjectMeta {
+        name: Some(role_name(fs)),
+        namespace: Some(target.to_owned()),
+        labels: Some(labels(fs)),
+        ..Default::default()
+    };
+    let role = Role {
+        metadata: metadata.clone(),
+        rules: Some(rules()),
+    };
+    let roles: Api<Role> = Api::namespaced(client.clone(), target);
+    roles
+        .patch(&role.name_any(), &params, &Patch::Apply(&role))
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+
+    let binding = RoleBinding {
+        metadata,
+        role_ref: RoleRef {
+            api_group: "rbac.authorization.k8s.io".to_owned(),
+            kind: "Role".to_owned(),
+            name: role_name(fs),
+        },
+        subjects: Some(vec![Subject {
+            kind: "ServiceAccount".to_owned(),
+            name: service_account_name(fs),
+            namespace: Some(ns),
+            ..Default::default()
+        }]),
+    };
+    let bindings: Api<RoleBinding> = Api::namespaced(client.clone(), target);
+    bindings
+        .patch(&binding.name_any(), &params, &Patch::Apply(&binding))
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+
+    revoke_except(client, fs, Some(target)).await
+}
+
+/// Removes the roles and bindings granted for a forwarded service, except those in the
+/// current target namespace. Owner references cannot cross namespaces, so these are
+/// not garbage collected.
+pub(crate) async fn revoke_except(
+    client: Client,
+    fs: &ForwardedService,
+    keep: Option<&str>,
+) -> Result<(), Error> {
+    let roles: Api<Role> = Api::all(client.clone());
+    for role in roles
+        .list_metadata(&selector(fs))
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?
+    {
+        let ns = role.namespace().unwrap_or_default();
+        if keep != Some(ns.as_str()) {
+            delete_if_exists(
+                Api::<Role>::namespaced(client.clone(), &ns),
+                &role.name_any(),
+            )
+            .await?;
+            delete_if_exists(
+                Api::<RoleBinding>::namespaced(client.clone(), &ns),
+                &role.name_any(),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::Namespace;
+    use kube::core::ObjectMeta;
+
+    use super::{allows, ANNOTATION_FORWARD_FROM};
+
+    fn namespace(name: &str, forward_from: Option<&str>) -> Namespace {
+        Namespace {
+            metadata: ObjectMeta {
+                name: Some(name.to_owned()),
+                annotations: forward_from
+                    .map(|from| [(ANNOTATION_FORWARD_FROM.to_owned(), from.to_owned())].into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_namespaces_opt_in_to_forwards() {
+        assert!(allows(&namespace("db", None), "db"));
+        assert!(!allows(&namespace("db", None), "team"));
+        assert!(allows(&namespace("db", Some("dev, team")), "team"));
+        assert!(!allows(&namespace("db", Some("dev,team")), "ops"));
+        assert!(allows(&namespace("db", Some("*")), "ops"));
+    }
+}
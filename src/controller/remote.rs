@@ -0,0 +1,429 @@
+use k8s_openapi::api::{
+    apps::v1::{Deployment, StatefulSet},
+    authorization::v1::{
+        ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec,
+        SubjectAccessReview, SubjectAccessReviewSpec, SubjectAccessReviewStatus,
+    },
+    core::v1::{Endpoints, Pod, Service},
+};
+use kube::{
+    api::{ListParams, PostParams},
+    Api, Client, ResourceExt,
+};
+
+use super::{credentials::Credentials, rbac::ServiceAccountUser, status};
+use crate::{
+    crd::{Condition, ForwardedPort, Target, TargetKind},
+    service::{is_ready, label_selector},
+};
+
+/// Reason and message of a passed or failed check
+pub(crate) type Check = Result<(&'static str, String), (&'static str, String)>;
+
+/// What the forwarder's credentials see of the remote target
+pub(crate) struct Checks {
+    /// Ports of the target, when it was found
+    pub ports: Option<Vec<ForwardedPort>>,
+    /// Desired replicas of a stateful set target
+    pub replicas: Option<i32>,
+    pub target_found: Check,
+    pub endpoints_ready: Check,
+    pub port_forward_allowed: Check,
+}
+
+impl Checks {
+    fn failed(reason: &'static str, message: String) -> Self {
+        Self {
+            ports: None,
+            replicas: None,
+            target_found: Err((reason, message.clone())),
+            endpoints_ready: Err((reason, message.clone())),
+            port_forward_allowed: Err((reason, message)),
+        }
+    }
+
+    /// Sets a condition per check, returning the first failure
+    pub fn record(&self, conditions: &mut Vec<Condition>) -> Result<(), String> {
+        let checks = [
+            (status::CONDITION_REMOTE_SERVICE_FOUND, &self.target_found),
+            (status::CONDITION_ENDPOINTS_READY, &self.endpoints_ready),
+            (
+                status::CONDITION_PORT_FORWARD_ALLOWED,
+                &self.port_forward_allowed,
+            ),
+        ];
+        let mut failure = None;
+        for (type_, check) in checks {
+            match check {
+                Ok((reason, message)) => {
+                    status::set_condition(conditions, type_, true, reason, message.clone())
+                }
+                Err((reason, message)) => {
+                    status::set_condition(conditions, type_, false, reason, message.clone());
+                    failure.get_or_insert_with(|| message.clone());
+                }
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    }
+}
+
+/// Checks with the `remote` client of the forwarder's credentials that the target exists
+/// in `namespace` and has ready pods, and that they may be port-forwarded. In-cluster
+/// credentials are reviewed for the `forwarder` service account, as the controller's
+/// own client reads the target.
+pub(crate) async fn check(
+    remote: Result<Client, String>,
+    credentials: &Credentials<'_>,
+    namespace: &str,
+    target: &Target,
+    forwarder: &ServiceAccountUser,
+) -> Checks {
+    if let Err(message) = target.validate() {
+        return Checks::failed("Invalid", message);
+    }
+    let remote = match remote {
+        Ok(remote) => remote,
+        Err(message) => return Checks::failed("Unreachable", message),
+    };
+    let found = match find(remote.clone(), namespace, target).await {
+        Ok(found) => found,
+        Err(e) => return Checks::failed("Unreachable", format!("unable to read {target}: {e}")),
+    };
+    let (target_found, endpoints_ready) = match &found {
+        Some(found) => (
+            Ok(("Found", format!("{target} exists in `{namespace}`"))),
+            found.ready.clone(),
+        ),
+        None => {
+            let message = format!("{target} not found in `{namespace}`");
+            (
+                Err(("NotFound", message.clone())),
+                Err(("TargetNotFound", message)),
+            )
+        }
+    };
+    let port_forward_allowed = match credentials {
+        Credentials::InCluster => service_account_allowed(remote, namespace, forwarder).await,
+        _ => port_forward_allowed(remote, namespace).await,
+    };
+    Checks {
+        replicas: found.as_ref().and_then(|f| f.replicas),
+        ports: found.map(|f| f.ports),
+        target_found,
+        endpoints_ready,
+        port_forward_allowed,
+    }
+}
+
+/// An existing target
+struct Found {
+    ports: Vec<ForwardedPort>,
+    ready: Check,
+    replicas: Option<i32>,
+}
+
+/// Ports and readiness of the target, `None` when it does not exist
+async fn find(
+    remote: Client,
+    namespace: &str,
+    target: &Target,
+) -> Result<Option<Found>, kube::Error> {
+    let name = target.name.as_deref().unwrap_or_default();
+    let mut replicas = None;
+    let selector = match target.kind {
+        TargetKind::Service => {
+            let Some(service) = Api::<Service>::namespaced(remote.clone(), namespace)
+                .get_opt(name)
+                .await?
+            else {
+                return Ok(None);
+            };
+            return Ok(Some(Found {
+                ports: forwarded_ports(&service),
+                ready: endpoints_ready(remote, namespace, name).await,
+                replicas: None,
+            }));
+        }
+        TargetKind::Pod => {
+            let Some(pod) = Api::<Pod>::namespaced(remote, namespace)
+                .get_opt(name)
+                .await?
+            else {
+                return Ok(None);
+            };
+            let ready = match is_ready(&pod) {
+                true => Ok(("Ready", format!("pod `{name}` is ready"))),
+                false => Err(("NotReady", format!("pod `{name}` is not ready"))),
+            };
+            return Ok(Some(Found {
+                ports: container_ports(&pod),
+                ready,
+                replicas: None,
+            }));
+        }
+        TargetKind::Deployment => Api::<Deployment>::namespaced(remote.clone(), namespace)
+            .get_opt(name)
+            .await?
+            .map(|d| {
+                d.spec
+                    .map(|s| label_selector(&s.selector))
+                    .unwrap_or_default()
+            }),
+        TargetKind::StatefulSet => Api::<StatefulSet>::namespaced(remote.clone(), namespace)
+            .get_opt(name)
+            .await?
+            .map(|s| {
+                let spec = s.spec.unwrap_or_default();
+                replicas = Some(spec.replicas.unwrap_or(1));
+                label_selector(&spec.selector)
+            }),
+        TargetKind::Selector | TargetKind::Leader => Some(target.selector()),
+    };
+    let Some(selector) = selector else {
+        return Ok(None);
+    };
+    let pods = Api::<Pod>::namespaced(remote, namespace)
+        .list(&ListParams::default().labels(&selector))
+        .await?;
+    if matches!(target.kind, TargetKind::Selector | TargetKind::Leader) && pods.items.is_empty() {
+        return Ok(None);
+    }
+    let ready: Vec<&Pod> = pods.iter().filter(|pod| is_ready(pod)).collect();
+    let ports = ready
+        .first()
+        .copied()
+        .or(pods.items.first())
+        .map(container_ports)
+        .unwrap_or_default();
+    let ready = match (target.kind, ready.as_slice()) {
+        (_, []) => Err(("NoReadyPods", format!("no ready pods match `{selector}`"))),
+        (TargetKind::Leader, [leader]) => {
+            Ok(("Elected", format!("pod `{}` leads", leader.name_any())))
+        }
+        (TargetKind::Leader, several) => Err((
+            "AmbiguousLeader",
+            format!(
+                "{} ready pods match `{selector}`, the forwarder keeps its current one",
+                several.len()
+            ),
+        )),
+        (_, ready) => Ok(("Ready", format!("{} ready pods", ready.len()))),
+    };
+    Ok(Some(Found {
+        ports,
+        ready,
+        replicas,
+    }))
+}
+
+async fn endpoints_ready(remote: Client, namespace: &str, name: &str) -> Check {
+    let endpoints = Api::<Endpoints>::namespaced(remote, namespace)
+        .get_opt(name)
+        .await
+        .map_err(|e| ("Unreachable", format!("unable to read endpoints: {e}")))?;
+    let ready = endpoints
+        .iter()
+        .flat_map(|e| e.subsets.iter().flatten())
+        .map(|subset| subset.addresses.as_ref().map_or(0, Vec::len))
+        .sum::<usize>();
+    match ready {
+        0 => Err((
+            "NoReadyEndpoints",
+            format!("service `{namespace}/{name}` has no ready endpoints"),
+        )),
+        ready => Ok(("Ready", format!("{ready} ready endpoints"))),
+    }
+}
+
+fn port_forward_attributes(namespace: &str) -> ResourceAttributes {
+    ResourceAttributes {
+        namespace: Some(namespace.to_owned()),
+        verb: Some("create".to_owned()),
+        resource: Some("pods".to_owned()),
+        subresource: Some("portforward".to_owned()),
+        ..Default::default()
+    }
+}
+
+fn reviewed(status: Option<SubjectAccessReviewStatus>, who: &str, namespace: &str) -> Check {
+    match status {
+        Some(status) if status.allowed => Ok((
+            "Allowed",
+            format!("{who} may port-forward pods in `{namespace}`"),
+        )),
+        status => Err((
+            "Forbidden",
+            format!(
+                "{who} may not port-forward pods in `{namespace}`{}",
+                status
+                    .and_then(|s| s.reason)
+                    .map(|r| format!(": {r}"))
+                    .unwrap_or_default()
+            ),
+        )),
+    }
+}
+
+async fn port_forward_allowed(remote: Client, namespace: &str) -> Check {
+    let review = SelfSubjectAccessReview {
+        spec: SelfSubjectAccessReviewSpec {
+            resource_attributes: Some(port_forward_attributes(namespace)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let review = Api::<SelfSubjectAccessReview>::all(remote)
+        .create(&PostParams::default(), &review)
+        .await
+        .map_err(|e| ("Unreachable", format!("unable to review access: {e}")))?;
+    reviewed(review.status, "credentials", namespace)
+}
+
+/// Reviews the access of the forwarder service account rather than the controller's
+async fn service_account_allowed(
+    local: Client,
+    namespace: &str,
+    forwarder: &ServiceAccountUser,
+) -> Check {
+    let review = SubjectAccessReview {
+        spec: SubjectAccessReviewSpec {
+            resource_attributes: Some(port_forward_attributes(namespace)),
+            user: Some(forwarder.user.clone()),
+            groups: Some(forwarder.groups.clone()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let review = Api::<SubjectAccessReview>::all(local)
+        .create(&PostParams::default(), &review)
+        .await
+        .map_err(|e| ("Unreachable", format!("unable to review access: {e}")))?;
+    reviewed(review.status, &format!("`{}`", forwarder.user), namespace)
+}
+
+/// TCP ports of a service, forwarded under the same number, name and protocol
+pub(crate) fn forwarded_ports(service: &Service) -> Vec<ForwardedPort> {
+    service
+        .spec
+        .iter()
+        .flat_map(|spec| spec.ports.iter().flatten())
+        .filter(|port| port.protocol.as_deref().unwrap_or("TCP") == "TCP")
+        .filter_map(|port| {
+            let number = u16::try_from(port.port).ok()?;
+            Some(ForwardedPort {
+                name: port
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("port-{number}")),
+                port: number,
+                remote_port: number,
+                app_protocol: port.app_protocol.clone(),
+            })
+        })
+        .collect()
+}
+
+/// TCP container ports of a pod
+fn container_ports(pod: &Pod) -> Vec<ForwardedPort> {
+    pod.spec
+        .iter()
+        .flat_map(|spec| spec.containers.iter())
+        .flat_map(|container| container.ports.iter().flatten())
+        .filter(|port| port.protocol.as_deref().unwrap_or("TCP") == "TCP")
+        .filter_map(|port| {
+            let number = u16::try_from(port.container_port).ok()?;
+            Some(ForwardedPort {
+                name: port
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("port-{number}")),
+                port: number,
+                remote_port: number,
+                app_protocol: None,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::{Service, ServicePort, ServiceSpec};
+
+    use super::{forwarded_ports, Checks};
+    use crate::crd::ForwardedPort;
+
+    #[test]
+    fn test_record_sets_a_condition_per_check() {
+        let checks = Checks {
+            ports: None,
+            replicas: None,
+            target_found: Ok(("Found", "found".to_owned())),
+            endpoints_ready: Err(("NoReadyEndpoints", "no endpoints".to_owned())),
+            port_forward_allowed: Err(("Forbidden", "forbidden".to_owned())),
+        };
+        let mut conditions = Vec::new();
+        assert_eq!(
+            Err("no endpoints".to_owned()),
+            checks.record(&mut conditions)
+        );
+        let statuses: Vec<(&str, &str)> = conditions
+            .iter()
+            .map(|c| (c.type_.as_str(), c.status.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("RemoteServiceFound", "True"),
+                ("EndpointsReady", "False"),
+                ("PortForwardAllowed", "False"),
+            ],
+            statuses
+        );
+    }
+
+    #[test]
+    fn test_forwarded_ports_keep_names_and_app_protocol() {
+        let service = Service {
+            spec: Some(ServiceSpec {
+                ports: Some(vec![
+                    ServicePort {
+                        name: Some("grpc".to_owned()),
+                        port: 9000,
+                        app_protocol: Some("kubernetes.io/h2c".to_owned()),
+                        ..Default::default()
+                    },
+                    ServicePort {
+                        name: Some("dns".to_owned()),
+                        port: 53,
+                        protocol: Some("UDP".to_owned()),
+                        ..Default::default()
+                    },
+                    ServicePort {
+                        port: 8080,
+                        protocol: Some("TCP".to_owned()),
+                        ..Default::default()
+                    },
+                ]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                ForwardedPort {
+                    name: "grpc".to_owned(),
+                    port: 9000,
+                    remote_port: 9000,
+                    app_protocol: Some("kubernetes.io/h2c".to_owned()),
+                },
+                ForwardedPort {
+                    name: "port-8080".to_owned(),
+                    port: 8080,
+                    remote_port: 8080,
+                    app_protocol: None,
+                },
+            ],
+            forwarded_ports(&service)
+        );
+    }
+}
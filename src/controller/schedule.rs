@@ -0,0 +1,241 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+
+use crate::crd::Schedule;
+
+/// Back-to-back windows followed at most when looking for the next closing
+const MAX_CHAINED: usize = 100;
+
+/// Whether a schedule is open at some point in time, and when that changes next
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Window {
+    pub open: bool,
+    pub next_transition: Option<DateTime<Utc>>,
+}
+
+struct Parsed {
+    starts: cron::Schedule,
+    duration: Duration,
+}
+
+fn parse(schedule: &Schedule) -> Result<(Tz, Vec<Parsed>), String> {
+    let time_zone = match &schedule.time_zone {
+        Some(tz) => tz
+            .parse::<Tz>()
+            .map_err(|e| format!("unknown time zone `{tz}`: {e}"))?,
+        None => Tz::UTC,
+    };
+    if schedule.windows.is_empty() {
+        return Err("the schedule has no windows".to_owned());
+    }
+    let windows = schedule
+        .windows
+        .iter()
+        .map(|window| {
+            // the cron crate expects a leading seconds field and numbers the days of the
+            // week from 1 for Sunday
+            let fields = window.start.split_whitespace().collect::<Vec<_>>();
+            let expression = match fields.as_slice() {
+                [minute, hour, day, month, day_of_week] => format!(
+                    "0 {minute} {hour} {day} {month} {}",
+                    day_of_week_field(day_of_week)
+                ),
+                _ => window.start.clone(),
+            };
+            let starts = cron::Schedule::from_str(&expression)
+                .map_err(|e| format!("invalid window start `{}`: {e}", window.start))?;
+            Ok(Parsed {
+                starts,
+                duration: Duration::minutes(i64::from(window.duration_minutes)),
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok((time_zone, windows))
+}
+
+/// Translates a standard cron day-of-week field, where Sunday is 0 or 7, to the
+/// numbering of the cron crate. Names and anything unparsable are kept as is.
+fn day_of_week_field(field: &str) -> String {
+    let day = |value: u32| {
+        if value == 0 || value == 7 {
+            1
+        } else {
+            value + 1
+        }
+    };
+    field
+        .split(',')
+        .map(|part| {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, Some(step)),
+                None => (part, None),
+            };
+            let with_step = |range: String| match step {
+                Some(step) => format!("{range}/{step}"),
+                None => range,
+            };
+            let Some((first, last)) = range.split_once('-') else {
+                return match range.parse::<u32>() {
+                    Ok(value) if value <= 7 => with_step(day(value).to_string()),
+                    _ => part.to_owned(),
+                };
+            };
+            match first.parse::<u32>().ok().zip(last.parse::<u32>().ok()) {
+                Some((first, last)) if first <= last && last <= 7 => {
+                    // a range through Saturday that ends on Sunday again
+                    let sunday_again = first > 0
+                        && last == 7
+                        && step
+                            .map_or(Some(1), |step| step.parse::<u32>().ok())
+                            .is_some_and(|step| step > 0 && (7 - first) % step == 0);
+                    let last = last.min(6);
+                    let translated = with_step(format!("{}-{}", day(first), day(last)));
+                    match sunday_again {
+                        true => format!("{translated},1"),
+                        false => translated,
+                    }
+                }
+                _ => part.to_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Closing of the latest opening of `window` before `at`, unless it already closed
+fn closes(window: &Parsed, time_zone: &Tz, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let since = (at - window.duration).with_timezone(time_zone);
+    window
+        .starts
+        .after(&since)
+        .map(|start| start.with_timezone(&Utc))
+        .take_while(|start| *start <= at)
+        .last()
+        .map(|start| start + window.duration)
+}
+
+/// Evaluates the schedule at `at`. Windows that open as another closes are followed, so
+/// the next transition is a real change.
+pub(crate) fn evaluate(schedule: &Schedule, at: DateTime<Utc>) -> Result<Window, String> {
+    let (time_zone, windows) = parse(schedule)?;
+    let closing = |at| {
+        windows
+            .iter()
+            .filter_map(|window| closes(window, &time_zone, at))
+            .max()
+    };
+    let Some(mut close) = closing(at) else {
+        let opens = windows
+            .iter()
+            .filter_map(|window| window.starts.after(&at.with_timezone(&time_zone)).next())
+            .map(|start| start.with_timezone(&Utc))
+            .min();
+        return Ok(Window {
+            open: false,
+            next_transition: opens,
+        });
+    };
+    for _ in 0..MAX_CHAINED {
+        match closing(close) {
+            Some(later) if later > close => close = later,
+            _ => break,
+        }
+    }
+    Ok(Window {
+        open: true,
+        next_transition: Some(close),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::{day_of_week_field, evaluate};
+    use crate::crd::{Schedule, ScheduleWindow};
+
+    fn business_hours() -> Schedule {
+        Schedule {
+            windows: vec![ScheduleWindow {
+                start: "0 8 * * Mon-Fri".to_owned(),
+                duration_minutes: 10 * 60,
+            }],
+            time_zone: Some("Europe/Berlin".to_owned()),
+        }
+    }
+
+    fn utc(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_evaluate_windows_in_time_zone() {
+        // Monday 8:00 in Berlin is 7:00 UTC in winter
+        let window = evaluate(&business_hours(), utc(8, 6)).unwrap();
+        assert!(!window.open);
+        assert_eq!(Some(utc(8, 7)), window.next_transition);
+
+        let window = evaluate(&business_hours(), utc(8, 7)).unwrap();
+        assert!(window.open);
+        assert_eq!(Some(utc(8, 17)), window.next_transition);
+
+        // from Friday evening to Monday morning
+        let window = evaluate(&business_hours(), utc(12, 20)).unwrap();
+        assert!(!window.open);
+        assert_eq!(Some(utc(15, 7)), window.next_transition);
+    }
+
+    #[test]
+    fn test_day_of_week_uses_standard_numbering() {
+        assert_eq!("2-6", day_of_week_field("1-5"));
+        assert_eq!("1", day_of_week_field("0"));
+        assert_eq!("1", day_of_week_field("7"));
+        assert_eq!("2-7,1", day_of_week_field("1-7"));
+        assert_eq!("Mon-Fri,*/2", day_of_week_field("Mon-Fri,*/2"));
+
+        let weekdays = |start: &str| Schedule {
+            windows: vec![ScheduleWindow {
+                start: start.to_owned(),
+                duration_minutes: 60,
+            }],
+            time_zone: None,
+        };
+        // 2024-01-07 is a Sunday, 2024-01-08 a Monday
+        assert!(evaluate(&weekdays("0 8 * * 1-5"), utc(8, 8)).unwrap().open);
+        assert!(!evaluate(&weekdays("0 8 * * 1-5"), utc(7, 8)).unwrap().open);
+        assert!(evaluate(&weekdays("0 8 * * 0"), utc(7, 8)).unwrap().open);
+        assert!(!evaluate(&weekdays("0 8 * * 0"), utc(8, 8)).unwrap().open);
+    }
+
+    #[test]
+    fn test_evaluate_follows_back_to_back_windows() {
+        let schedule = Schedule {
+            windows: vec![
+                ScheduleWindow {
+                    start: "0 8 * * *".to_owned(),
+                    duration_minutes: 4 * 60,
+                },
+                ScheduleWindow {
+                    start: "0 12 * * *".to_owned(),
+                    duration_minutes: 6 * 60,
+                },
+            ],
+            time_zone: None,
+        };
+        let window = evaluate(&schedule, utc(8, 9)).unwrap();
+        assert!(window.open);
+        assert_eq!(Some(utc(8, 18)), window.next_transition);
+    }
+
+    #[test]
+    fn test_evaluate_rejects_invalid_schedules() {
+        let mut schedule = business_hours();
+        schedule.time_zone = Some("Mars/Olympus".to_owned());
+        assert!(evaluate(&schedule, utc(8, 6)).is_err());
+        schedule.time_zone = None;
+        schedule.windows[0].start = "every morning".to_owned();
+        assert!(evaluate(&schedule, utc(8, 6)).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{ConfigMap, ConfigMapVolumeSource, Volume, VolumeMount};
+use kube::{
+    api::{Patch, PatchParams},
+    core::ObjectMeta,
+    Api, Client, ResourceExt,
+};
+
+use super::{
+    delete_if_exists,
+    failover::{mount_clusters, primary_name},
+    owner_reference,
+};
+use crate::{
+    crd::{ForwardedService, RemoteCluster},
+    error::Error,
+    service::SplitConfig,
+};
+
+const WEIGHTS_PATH: &str = "/etc/port-forward-operator/weights";
+const WEIGHTS_KEY: &str = "weights.json";
+
+fn config_map_name(fs: &ForwardedService) -> String {
+    format!("{}-weights", fs.name_any())
+}
+
+/// Weight of every cluster by the name the forwarder knows it under
+fn weights(fs: &ForwardedService) -> BTreeMap<String, u32> {
+    let Some(split) = &fs.spec.traffic_split else {
+        return BTreeMap::new();
+    };
+    let mut weights = BTreeMap::from([(primary_name(fs), split.weight)]);
+    for cluster in &split.clusters {
+        weights.insert(cluster.cluster_ref.clone(), cluster.weight);
+    }
+    weights
+}
+
+/// Keeps the weights the forwarder reloads in a config map, removing it once traffic
+/// is no longer split
+pub(crate) async fn sync(client: Client, fs: &ForwardedService) -> Result<(), Error> {
+    let ns = fs.namespace().unwrap();
+    let config_maps: Api<ConfigMap> = Api::namespaced(client, &ns);
+    if fs.spec.traffic_split.is_none() {
+        return delete_if_exists(config_maps, &config_map_name(fs)).await;
+    }
+    let config_map = ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(config_map_name(fs)),
+            namespace: Some(ns),
+            owner_references: Some(vec![owner_reference(fs)]),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(
+            WEIGHTS_KEY.to_owned(),
+            serde_json::to_string(&weights(fs)).expect("serializable weights"),
+        )])),
+        ..Default::default()
+    };
+    config_maps
+        .patch(
+            &config_map_name(fs),
+            &PatchParams::apply("port-forward-operator").force(),
+            &Patch::Apply(&config_map),
+        )
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+    Ok(())
+}
+
+/// Argument passing the split clusters to the forwarder, and the mounts of their
+/// credentials and of the weights
+pub(crate) fn forwarder(
+    fs: &ForwardedService,
+    clusters: &[RemoteCluster],
+) -> (Vec<String>, Vec<VolumeMount>, Vec<Volume>) {
+    let (clusters, mut volume_mounts, mut volumes) = mount_clusters(clusters);
+    let config = SplitConfig {
+        primary: primary_name(fs),
+        clusters,
+        weights: format!("{WEIGHTS_PATH}/{WEIGHTS_KEY}"),
+    };
+    volume_mounts.push(VolumeMount {
+        mount_path: WEIGHTS_PATH.to_owned(),
+        name: "weights".to_owned(),
+        read_only: Some(true),
+        ..Default::default()
+    });
+    volumes.push(Volume {
+        name: "weights".to_owned(),
+        config_map: Some(ConfigMapVolumeSource {
+            optional: Some(false),
+            name: Some(config_map_name(fs)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    let args = vec![
+        "--split".to_owned(),
+        serde_json::to_string(&config).expect("serializable split config"),
+    ];
+    (args, volume_mounts, volumes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::weights;
+    use crate::crd::{ForwardedService, ForwardedServiceSpec, TrafficSplit, WeightedCluster};
+
+    #[test]
+    fn test_weights_by_cluster_name() {
+        let fs = ForwardedService::new(
+            "postgres",
+            ForwardedServiceSpec {
+                service: "postgres".to_owned(),
+                cluster_ref: Some("eu-west".to_owned()),
+                traffic_split: Some(TrafficSplit {
+                    weight: 90,
+                    clusters: vec![WeightedCluster {
+                        cluster_ref: "eu-central".to_owned(),
+                        weight: 10,
+                    }],
+                }),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            BTreeMap::from([("eu-central".to_owned(), 10), ("eu-west".to_owned(), 90)]),
+            weights(&fs)
+        );
+    }
+}
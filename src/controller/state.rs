@@ -3,7 +3,7 @@ use tokio::sync::RwLock;
 
 use kube::Client;
 
-use super::{Context, Diagnostics};
+use super::{credentials::Clients, index::References, Context, Diagnostics};
 
 pub struct State {
     /// Diagnostics populated by the reconciler
@@ -25,6 +25,9 @@ impl State {
             // metrics: Metrics::default().register(&self.registry).unwrap(),
             diagnostics: self.diagnostics.clone(),
             image: self.image.clone(),
+            references: Arc::new(References::default()),
+            cluster_references: Arc::new(References::default()),
+            clients: Arc::new(Clients::default()),
         })
     }
 }
@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use kube::{
+    api::{Patch, PatchParams},
+    Api, Resource, ResourceExt,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{crd::Condition, error::Error};
+
+pub(crate) const CONDITION_CREDENTIALS_VALID: &str = "CredentialsValid";
+pub(crate) const CONDITION_READY: &str = "Ready";
+pub(crate) const CONDITION_PORTS_RESOLVED: &str = "PortsResolved";
+pub(crate) const CONDITION_REMOTE_SERVICE_FOUND: &str = "RemoteServiceFound";
+pub(crate) const CONDITION_ENDPOINTS_READY: &str = "EndpointsReady";
+pub(crate) const CONDITION_PORT_FORWARD_ALLOWED: &str = "PortForwardAllowed";
+pub(crate) const CONDITION_SUSPENDED: &str = "Suspended";
+pub(crate) const CONDITION_IN_SCHEDULE: &str = "InSchedule";
+pub(crate) const CONDITION_EXPIRING: &str = "Expiring";
+pub(crate) const CONDITION_SERVICE_NAME_VALID: &str = "ServiceNameValid";
+pub(crate) const CONDITION_CONFLICTING: &str = "Conflicting";
+pub(crate) const CONDITION_POOLED: &str = "Pooled";
+
+/// Sets a condition, keeping its transition time when the status did not change
+pub(crate) fn set_condition(
+    conditions: &mut Vec<Condition>,
+    type_: &str,
+    status: bool,
+    reason: &str,
+    message: impl Into<String>,
+) {
+    let status = if status { "True" } else { "False" };
+    let mut condition = Condition {
+        type_: type_.to_owned(),
+        status: status.to_owned(),
+        reason: Some(reason.to_owned()),
+        message: Some(message.into()),
+        last_transition_time: Some(Utc::now()),
+    };
+    match conditions.iter_mut().find(|c| c.type_ == type_) {
+        Some(existing) => {
+            if existing.status == condition.status {
+                condition.last_transition_time = existing.last_transition_time;
+            }
+            *existing = condition;
+        }
+        None => conditions.push(condition),
+    }
+}
+
+/// Whether the condition of type `type_` is set and true
+pub(crate) fn is_true(conditions: &[Condition], type_: &str) -> bool {
+    conditions
+        .iter()
+        .any(|c| c.type_ == type_ && c.status == "True")
+}
+
+/// Sets the `CredentialsValid` condition and the expiry from a credential inspection,
+/// returning the expiry of usable credentials
+pub(crate) fn check_credentials(
+    inspected: Result<Option<DateTime<Utc>>, (&'static str, String)>,
+    conditions: &mut Vec<Condition>,
+    expires_at: &mut Option<DateTime<Utc>>,
+) -> Result<Option<DateTime<Utc>>, String> {
+    let checked = inspected.and_then(|expiry| match expiry {
+        Some(expiry) if expiry <= Utc::now() => {
+            Err(("Expired", format!("credentials expired at {expiry}")))
+        }
+        _ => Ok(expiry),
+    });
+
+    match checked {
+        Ok(expiry) => {
+            *expires_at = expiry;
+            set_condition(
+                conditions,
+                CONDITION_CREDENTIALS_VALID,
+                true,
+                "Valid",
+                "remote cluster credentials are usable",
+            );
+            Ok(expiry)
+        }
+        Err((reason, message)) => {
+            *expires_at = None;
+            set_condition(
+                conditions,
+                CONDITION_CREDENTIALS_VALID,
+                false,
+                reason,
+                message.clone(),
+            );
+            Err(message)
+        }
+    }
+}
+
+pub(crate) async fn patch_status<K, S>(api: &Api<K>, object: &K, status: &S) -> Result<(), Error>
+where
+    K: Resource + Clone + DeserializeOwned + std::fmt::Debug,
+    S: Serialize,
+{
+    api.patch_status(
+        &object.name_any(),
+        &PatchParams::default(),
+        &Patch::Merge(serde_json::json!({ "status": status })),
+    )
+    .await
+    .map_err(|e| Error::Kubernetes { source: e })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::set_condition;
+
+    #[test]
+    fn test_set_condition_keeps_transition_time() {
+        let mut conditions = Vec::new();
+        set_condition(&mut conditions, "Ready", true, "Valid", "first");
+        let first = conditions[0].last_transition_time;
+        set_condition(&mut conditions, "Ready", true, "Valid", "second");
+        assert_eq!(1, conditions.len());
+        assert_eq!(first, conditions[0].last_transition_time);
+        assert_eq!(Some("second".to_owned()), conditions[0].message);
+    }
+
+    #[test]
+    fn test_set_condition_updates_transition_time() {
+        let mut conditions = Vec::new();
+        set_condition(&mut conditions, "Ready", true, "Valid", "first");
+        conditions[0].last_transition_time = None;
+        set_condition(&mut conditions, "Ready", false, "Invalid", "second");
+        assert_eq!("False", conditions[0].status);
+        assert!(conditions[0].last_transition_time.is_some());
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use k8s_openapi::{
+    api::{
+        admissionregistration::v1::{MutatingWebhookConfiguration, ValidatingWebhookConfiguration},
+        core::v1::Secret,
+    },
+    apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition,
+    ByteString,
+};
+use kube::{
+    api::{ListParams, Patch, PatchParams, PostParams},
+    core::ObjectMeta,
+    Api, Client, CustomResourceExt, ResourceExt,
+};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+
+use crate::{
+    crd::{ForwardedService, RemoteCluster, SecretReferenceGrant, ServiceMirror},
+    error::Error,
+};
+
+/// Label that marks webhook configurations whose `caBundle` is managed by the controller
+pub const INJECT_CA_BUNDLE_LABEL: &str = "port-forward-operator.rs/inject-ca-bundle";
+/// Label of the certificate secret when the controller issued it, and may rotate it
+const SELF_ISSUED_LABEL: &str = "port-forward-operator.rs/self-issued";
+const FIELD_MANAGER: &str = "port-forward-operator";
+
+const CA_CERT_KEY: &str = "ca.crt";
+const CA_KEY_KEY: &str = "ca.key";
+const TLS_CERT_KEY: &str = "tls.crt";
+const TLS_KEY_KEY: &str = "tls.key";
+
+const CA_VALIDITY_DAYS: i64 = 3650;
+const CERT_VALIDITY_DAYS: i64 = 365;
+const ROTATE_BEFORE_DAYS: i64 = 30;
+
+pub struct TlsOptions {
+    /// Secret in the controller namespace that stores the certificates
+    pub secret: String,
+    /// Name of the Service fronting the webhook endpoint
+    pub service: String,
+    /// Namespace the controller runs in
+    pub namespace: String,
+}
+
+/// PEM encoded certificate material served by the webhook endpoint
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsBundle {
+    pub ca_cert: String,
+    /// Only present when the controller issued the CA itself
+    pub ca_key: Option<String>,
+    pub cert: String,
+    pub key: String,
+}
+
+impl TlsBundle {
+    fn from_secret(secret: &Secret) -> Option<Self> {
+        let data = secret.data.as_ref()?;
+        let get = |key: &str| {
+            data.get(key)
+                .and_then(|v| String::from_utf8(v.0.clone()).ok())
+        };
+        let cert = get(TLS_CERT_KEY)?;
+        let key = get(TLS_KEY_KEY)?;
+        Some(Self {
+            ca_cert: get(CA_CERT_KEY).unwrap_or_else(|| cert.clone()),
+            ca_key: get(CA_KEY_KEY),
+            cert,
+            key,
+        })
+    }
+
+    fn to_secret_data(&self) -> BTreeMap<String, ByteString> {
+        let mut data = BTreeMap::new();
+        data.insert(
+            CA_CERT_KEY.to_owned(),
+            ByteString(self.ca_cert.clone().into_bytes()),
+        );
+        if let Some(ca_key) = &self.ca_key {
+            data.insert(
+                CA_KEY_KEY.to_owned(),
+                ByteString(ca_key.clone().into_bytes()),
+            );
+        }
+        data.insert(
+            TLS_CERT_KEY.to_owned(),
+            ByteString(self.cert.clone().into_bytes()),
+        );
+        data.insert(
+            TLS_KEY_KEY.to_owned(),
+            ByteString(self.key.clone().into_bytes()),
+        );
+        data
+    }
+
+    /// Whether the bundle can be served until the next rotation check
+    fn is_current(&self, now: DateTime<Utc>) -> bool {
+        match certificate_expiry(&self.cert) {
+            Some(expiry) => expiry - now > Duration::days(ROTATE_BEFORE_DAYS),
+            None => false,
+        }
+    }
+
+    fn issuer(&self, now: DateTime<Utc>) -> Option<Certificate> {
+        let ca_key = self.ca_key.as_ref()?;
+        match certificate_expiry(&self.ca_cert) {
+            Some(expiry) if expiry - now > Duration::days(ROTATE_BEFORE_DAYS) => {}
+            _ => return None,
+        }
+        let key_pair = KeyPair::from_pem(ca_key).ok()?;
+        let params = CertificateParams::from_ca_cert_pem(&self.ca_cert, key_pair).ok()?;
+        Certificate::from_params(params).ok()
+    }
+}
+
+/// Returns the expiry of the first certificate in a PEM document
+pub fn certificate_expiry(pem: &str) -> Option<DateTime<Utc>> {
+    let (_, pem) = x509_parser::pem::parse_x509_pem(pem.as_bytes()).ok()?;
+    let certificate = pem.parse_x509().ok()?;
+    Utc.timestamp_opt(certificate.validity().not_after.timestamp(), 0)
+        .single()
+}
+
+fn tls_error(e: impl std::fmt::Display) -> Error {
+    Error::Tls(e.to_string())
+}
+
+fn validity(days: i64) -> (time::OffsetDateTime, time::OffsetDateTime) {
+    let now = time::OffsetDateTime::now_utc();
+    (
+        now - time::Duration::hours(1),
+        now + time::Duration::days(days),
+    )
+}
+
+fn generate_ca() -> Result<Certificate, Error> {
+    let mut params = CertificateParams::default();
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, "port-forward-operator-ca");
+    params.distinguished_name = name;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    (params.not_before, params.not_after) = validity(CA_VALIDITY_DAYS);
+    Certificate::from_params(params).map_err(tls_error)
+}
+
+fn generate_bundle(
+    options: &TlsOptions,
+    existing: Option<&TlsBundle>,
+    now: DateTime<Utc>,
+) -> Result<TlsBundle, Error> {
+    let (ca, ca_cert) = match existing.and_then(|b| b.issuer(now).map(|ca| (ca, b))) {
+        Some((ca, bundle)) => (ca, bundle.ca_cert.clone()),
+        None => {
+            let ca = generate_ca()?;
+            let pem = ca.serialize_pem().map_err(tls_error)?;
+            (ca, pem)
+        }
+    };
+
+    let service = &options.service;
+    let namespace = &options.namespace;
+    let mut params = CertificateParams::new(vec![
+        service.clone(),
+        format!("{service}.{namespace}"),
+        format!("{service}.{namespace}.svc"),
+        format!("{service}.{namespace}.svc.cluster.local"),
+    ]);
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, format!("{service}.{namespace}.svc"));
+    params.distinguished_name = name;
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    (params.not_before, params.not_after) = validity(CERT_VALIDITY_DAYS);
+    let cert = Certificate::from_params(params).map_err(tls_error)?;
+
+    Ok(TlsBundle {
+        ca_cert,
+        ca_key: Some(ca.serialize_private_key_pem()),
+        cert: cert.serialize_pem_with_signer(&ca).map_err(tls_error)?,
+        key: cert.serialize_private_key_pem(),
+    })
+}
+
+/// Whether the controller issued the certificates of the secret, so it may rotate them
+fn is_self_issued(secret: &Secret) -> bool {
+    secret.labels().get(SELF_ISSUED_LABEL).map(String::as_str) == Some("true")
+}
+
+/// Loads the serving certificate from the configured secret, which every replica shares.
+///
+/// A missing secret is issued by the controller, and only such secrets are rotated when
+/// close to expiry. Certificates of an external issuer, such as cert-manager, are served
+/// as they are and rotated by that issuer.
+pub async fn ensure_bundle(client: &Client, options: &TlsOptions) -> Result<TlsBundle, Error> {
+    let now = Utc::now();
+    let secrets: Api<Secret> = Api::namespaced(client.clone(), &options.namespace);
+    let name = &options.secret;
+    let stored = secrets
+        .get_opt(name)
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+
+    if let Some(secret) = stored.as_ref().filter(|s| !is_self_issued(s)) {
+        let bundle = TlsBundle::from_secret(secret).ok_or_else(|| {
+            Error::Tls(format!(
+                "secret `{name}` has no `{TLS_CERT_KEY}` and `{TLS_KEY_KEY}`"
+            ))
+        })?;
+        if !bundle.is_current(now) {
+            tracing::warn!(
+                "webhook certificate in secret `{}` expires soon, it is rotated by its issuer",
+                name
+            );
+        }
+        return Ok(bundle);
+    }
+    let existing = stored.as_ref().and_then(TlsBundle::from_secret);
+    if let Some(bundle) = existing.as_ref().filter(|b| b.is_current(now)) {
+        return Ok(bundle.clone());
+    }
+
+    tracing::info!("issuing webhook serving certificate");
+    let bundle = generate_bundle(options, existing.as_ref(), now)?;
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            namespace: Some(options.namespace.clone()),
+            labels: Some(BTreeMap::from([(
+                SELF_ISSUED_LABEL.to_owned(),
+                "true".to_owned(),
+            )])),
+            // another replica rotating at the same time makes the write fail
+            resource_version: stored.as_ref().and_then(|s| s.resource_version()),
+            ..Default::default()
+        },
+        data: Some(bundle.to_secret_data()),
+        type_: Some("kubernetes.io/tls".to_owned()),
+        ..Default::default()
+    };
+    let params = PostParams {
+        field_manager: Some(FIELD_MANAGER.to_owned()),
+        ..Default::default()
+    };
+    let written = match stored {
+        Some(_) => secrets.replace(name, &params, &secret).await,
+        None => secrets.create(&params, &secret).await,
+    };
+    match written {
+        Ok(_) => Ok(bundle),
+        // the other replica's certificates are served instead
+        Err(kube::Error::Api(r)) if r.code == 409 => secrets
+            .get(name)
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })
+            .and_then(|s| {
+                TlsBundle::from_secret(&s)
+                    .ok_or_else(|| Error::Tls(format!("secret `{name}` has no certificate")))
+            }),
+        Err(e) => Err(Error::Kubernetes { source: e }),
+    }
+}
+
+fn webhooks_patch(names: impl Iterator<Item = String>, ca_cert: &str) -> Patch<serde_json::Value> {
+    let ca_bundle = ByteString(ca_cert.as_bytes().to_vec());
+    let webhooks = names
+        .map(|name| serde_json::json!({ "name": name, "clientConfig": { "caBundle": ca_bundle } }))
+        .collect::<Vec<_>>();
+    Patch::Strategic(serde_json::json!({ "webhooks": webhooks }))
+}
+
+/// Writes the CA certificate into the webhook configurations and CRD conversion
+/// settings that point back at the controller
+pub async fn inject_ca_bundle(client: &Client, ca_cert: &str) -> Result<(), Error> {
+    let params = PatchParams::default();
+    let selector = ListParams::default().labels(&format!("{INJECT_CA_BUNDLE_LABEL}=true"));
+
+    let validating: Api<ValidatingWebhookConfiguration> = Api::all(client.clone());
+    for config in validating
+        .list(&selector)
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?
+    {
+        let names = config.webhooks.iter().flatten().map(|w| w.name.clone());
+        validating
+            .patch(&config.name_any(), &params, &webhooks_patch(names, ca_cert))
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?;
+    }
+
+    let mutating: Api<MutatingWebhookConfiguration> = Api::all(client.clone());
+    for config in mutating
+        .list(&selector)
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?
+    {
+        let names = config.webhooks.iter().flatten().map(|w| w.name.clone());
+        mutating
+            .patch(&config.name_any(), &params, &webhooks_patch(names, ca_cert))
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?;
+    }
+
+    let crds: Api<CustomResourceDefinition> = Api::all(client.clone());
+    for name in [
+        ForwardedService::crd_name(),
+        RemoteCluster::crd_name(),
+        ServiceMirror::crd_name(),
+        SecretReferenceGrant::crd_name(),
+    ] {
+        let crd = match crds
+            .get_opt(name)
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?
+        {
+            Some(crd) => crd,
+            None => continue,
+        };
+        let uses_webhook = crd
+            .spec
+            .conversion
+            .as_ref()
+            .map(|c| c.strategy == "Webhook" && c.webhook.is_some())
+            .unwrap_or_default();
+        if uses_webhook {
+            let patch = serde_json::json!({
+                "spec": { "conversion": { "webhook": { "clientConfig": {
+                    "caBundle": ByteString(ca_cert.as_bytes().to_vec())
+                } } } }
+            });
+            crds.patch(name, &params, &Patch::Merge(patch))
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::{
+        api::core::v1::Secret, apimachinery::pkg::apis::meta::v1::ManagedFieldsEntry,
+    };
+    use kube::core::ObjectMeta;
+
+    use super::{
+        certificate_expiry, generate_bundle, is_self_issued, TlsBundle, TlsOptions,
+        SELF_ISSUED_LABEL,
+    };
+
+    fn options() -> TlsOptions {
+        TlsOptions {
+            secret: "port-forward-operator-webhook-tls".to_owned(),
+            service: "port-forward-operator".to_owned(),
+            namespace: "port-forward-operator-system".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_generated_bundle_is_current() {
+        let now = Utc::now();
+        let bundle = generate_bundle(&options(), None, now).unwrap();
+        let expiry = certificate_expiry(&bundle.cert).unwrap();
+        assert!(expiry > now + Duration::days(300));
+        assert!(bundle.is_current(now));
+        assert!(!bundle.is_current(now + Duration::days(350)));
+    }
+
+    #[test]
+    fn test_rotation_reuses_valid_ca() {
+        let now = Utc::now();
+        let first = generate_bundle(&options(), None, now).unwrap();
+        let second = generate_bundle(&options(), Some(&first), now).unwrap();
+        assert_eq!(first.ca_cert, second.ca_cert);
+        assert_ne!(first.cert, second.cert);
+    }
+
+    #[test]
+    fn test_rotation_without_ca_key_issues_new_ca() {
+        let now = Utc::now();
+        let external = TlsBundle {
+            ca_key: None,
+            ..generate_bundle(&options(), None, now).unwrap()
+        };
+        let rotated = generate_bundle(&options(), Some(&external), now).unwrap();
+        assert_ne!(external.ca_cert, rotated.ca_cert);
+        assert!(rotated.ca_key.is_some());
+    }
+
+    #[test]
+    fn test_only_self_issued_secrets_are_rotated() {
+        let secret = |labels: &[(&str, &str)], manager: Option<&str>| Secret {
+            metadata: ObjectMeta {
+                labels: Some(
+                    labels
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect::<BTreeMap<_, _>>(),
+                ),
+                managed_fields: manager.map(|manager| {
+                    vec![ManagedFieldsEntry {
+                        manager: Some(manager.to_owned()),
+                        ..Default::default()
+                    }]
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(is_self_issued(&secret(
+            &[(SELF_ISSUED_LABEL, "true")],
+            None
+        )));
+        // writing to a secret does not make the controller its issuer
+        assert!(!is_self_issued(&secret(&[], Some("port-forward-operator"))));
+        assert!(!is_self_issued(&secret(
+            &[("controller.cert-manager.io/fao", "true")],
+            Some("cert-manager-certificates-issuing")
+        )));
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use k8s_openapi::{
+    api::{
+        authentication::v1::{TokenRequest, TokenRequestSpec},
+        core::v1::{Secret, ServiceAccount},
+    },
+    ByteString,
+};
+use kube::{
+    api::{Patch, PatchParams, PostParams},
+    core::ObjectMeta,
+    Api, Client, Resource, ResourceExt,
+};
+
+use super::{
+    credentials::{self, token_expiry, Credentials},
+    owner_reference,
+};
+use crate::{crd::TokenRequestReference, error::Error};
+
+const DEFAULT_EXPIRATION_SECONDS: i64 = 3600;
+
+/// Tokens are replaced once less than this fraction of their lifetime remains
+const REFRESH_DIVISOR: i32 = 3;
+
+fn lifetime(reference: &TokenRequestReference) -> Duration {
+    Duration::seconds(
+        reference
+            .expiration_seconds
+            .unwrap_or(DEFAULT_EXPIRATION_SECONDS),
+    )
+}
+
+/// When a token expiring at `expiry` should be replaced
+pub(crate) fn refresh_at(
+    reference: &TokenRequestReference,
+    expiry: DateTime<Utc>,
+) -> DateTime<Utc> {
+    expiry - lifetime(reference) / REFRESH_DIVISOR
+}
+
+fn pem_certificate(der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut pem = "-----BEGIN CERTIFICATE-----\n".to_owned();
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
+/// How long to wait before checking a token expiring at `expiry` again, at most `interval`
+pub(crate) fn requeue_after(
+    reference: &TokenRequestReference,
+    expiry: DateTime<Utc>,
+    interval: std::time::Duration,
+) -> std::time::Duration {
+    let refresh_in = (refresh_at(reference, expiry) - Utc::now())
+        .to_std()
+        .unwrap_or_default();
+    interval.min(refresh_in.max(std::time::Duration::from_secs(1)))
+}
+
+/// Makes sure the generated secret holds a token that is not due for refresh, minting a
+/// new one with the bootstrap credentials when needed. The secret is owned by `owner`
+/// and the service account namespace defaults to `target_namespace`. Returns the token
+/// expiry, or the reason and message why no token could be minted.
+pub(crate) async fn refresh<K>(
+    client: Client,
+    owner: &K,
+    target_namespace: &str,
+    credentials: &Credentials<'_>,
+) -> Result<Result<DateTime<Utc>, (&'static str, String)>, Error>
+where
+    K: Resource<DynamicType = ()>,
+{
+    let (reference, bootstrap, generated) = match credentials {
+        Credentials::TokenRequest {
+            reference,
+            bootstrap,
+            generated,
+        } => (*reference, bootstrap.as_ref(), generated),
+        _ => unreachable!("only token request credentials are refreshed"),
+    };
+    let ns = owner.namespace().unwrap();
+    let secrets: Api<Secret> = Api::namespaced(client.clone(), &ns);
+    let existing = secrets
+        .get_opt(&generated.secret)
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?
+        .and_then(|s| s.data)
+        .and_then(|data| data.get(&generated.token_key_any()).cloned())
+        .and_then(|token| token_expiry(&String::from_utf8_lossy(&token.0)));
+    if let Some(expiry) = existing.filter(|e| refresh_at(reference, *e) > Utc::now()) {
+        return Ok(Ok(expiry));
+    }
+
+    let inspection = credentials::inspect(client.clone(), &ns, bootstrap).await?;
+    let config = match inspection.config(bootstrap).await {
+        Ok(config) => config,
+        Err(message) => return Ok(Err(("InvalidBootstrap", message))),
+    };
+    let server = config.cluster_url.to_string();
+    let certificate_authority = config
+        .root_cert
+        .iter()
+        .flatten()
+        .map(|der| pem_certificate(der))
+        .collect::<String>();
+    let remote = match Client::try_from(config) {
+        Ok(remote) => remote,
+        Err(e) => return Ok(Err(("InvalidBootstrap", e.to_string()))),
+    };
+
+    let namespace = reference
+        .service_account_namespace
+        .clone()
+        .unwrap_or_else(|| target_namespace.to_owned());
+    let service_accounts: Api<ServiceAccount> = Api::namespaced(remote, &namespace);
+    let request = TokenRequest {
+        spec: TokenRequestSpec {
+            audiences: reference.audiences.clone(),
+            expiration_seconds: Some(lifetime(reference).num_seconds()),
+            bound_object_ref: None,
+        },
+        ..Default::default()
+    };
+    let minted = match service_accounts
+        .create_token_request(&reference.service_account, &PostParams::default(), &request)
+        .await
+    {
+        Ok(minted) => minted,
+        Err(e) => {
+            return Ok(Err((
+                "TokenRequestFailed",
+                format!(
+                    "unable to mint a token for service account `{namespace}/{}`: {e}",
+                    reference.service_account
+                ),
+            )))
+        }
+    };
+    let status = match minted.status {
+        Some(status) => status,
+        None => return Ok(Err(("TokenRequestFailed", "no token issued".to_owned()))),
+    };
+
+    tracing::info!(
+        "minted token for {}/{} expiring at {}",
+        &namespace,
+        &reference.service_account,
+        status.expiration_timestamp.0
+    );
+    let mut data = BTreeMap::from([
+        (generated.server_key_any(), ByteString(server.into_bytes())),
+        (
+            generated.token_key_any(),
+            ByteString(status.token.into_bytes()),
+        ),
+    ]);
+    if !certificate_authority.is_empty() {
+        data.insert(
+            generated.certificate_authority_key_any(),
+            ByteString(certificate_authority.into_bytes()),
+        );
+    }
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some(generated.secret.clone()),
+            namespace: Some(ns),
+            owner_references: Some(vec![owner_reference(owner)]),
+            ..Default::default()
+        },
+        data: Some(data),
+        ..Default::default()
+    };
+    secrets
+        .patch(
+            &generated.secret,
+            &PatchParams::apply("port-forward-operator").force(),
+            &Patch::Apply(&secret),
+        )
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+    Ok(Ok(status.expiration_timestamp.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{pem_certificate, refresh_at};
+    use crate::crd::TokenRequestReference;
+
+    #[test]
+    fn test_refresh_after_two_thirds_of_lifetime() {
+        let reference = TokenRequestReference {
+            expiration_seconds: Some(3600),
+            ..Default::default()
+        };
+        let expiry = Utc.timestamp_opt(10_000, 0).unwrap();
+        assert_eq!(8_800, refresh_at(&reference, expiry).timestamp());
+    }
+
+    #[test]
+    fn test_pem_certificate_wraps_lines() {
+        let pem = pem_certificate(&[0u8; 60]);
+        let lines: Vec<_> = pem.lines().collect();
+        assert_eq!("-----BEGIN CERTIFICATE-----", lines[0]);
+        assert_eq!(64, lines[1].len());
+        assert_eq!("-----END CERTIFICATE-----", lines[lines.len() - 1]);
+    }
+}
@@ -1,6 +1,10 @@
 use std::collections::BTreeMap;
 
-use kube::CustomResource;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{
+    Affinity, EnvVar, LocalObjectReference, ResourceRequirements, Toleration,
+};
+use kube::{CustomResource, ResourceExt};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -8,9 +12,11 @@ use serde::{Deserialize, Serialize};
 pub static FORWARDED_SERVICE_FINALIZER: &str = "forwardedservices.port-forward-operator.rs";
 #[allow(dead_code)]
 pub const ANNOTATION_GENERATION: &str = "port-forward-operator.rs/observed-generation";
+#[allow(dead_code)]
+pub const ANNOTATION_KUBECONFIG_HASH: &str = "port-forward-operator.rs/kubeconfig-hash";
 
-#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
-#[cfg_attr(test, derive(Default))]
+/// A local service forwarding to a service or pods of a remote cluster
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
 #[kube(
     kind = "ForwardedService",
     group = "port-forward-operator.rs",
@@ -18,12 +24,331 @@ pub const ANNOTATION_GENERATION: &str = "port-forward-operator.rs/observed-gener
     namespaced
 )]
 #[kube(status = "ForwardedServiceStatus", shortname = "fwd")]
+#[kube(
+    printcolumn = r#"{"name":"Service", "type":"string", "jsonPath":".status.service_name"}"#,
+    printcolumn = r#"{"name":"Expires", "type":"date", "description":"When the forward expires", "jsonPath":".status.lifetime_ends_at"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#
+)]
 pub struct ForwardedServiceSpec {
+    /// Remote service, unless `target` is set
+    #[serde(default)]
     pub service: String,
+    /// Remote pods to forward to, instead of `service`
+    pub target: Option<Target>,
+    /// Namespace of the remote service. Within the local cluster, any other namespace
+    /// than this one has to list it in its `port-forward-operator.rs/forward-from`
+    /// annotation.
     pub namespace: Option<String>,
-    #[schemars(length(min = 1), schema_with = "ports")]
+    /// Ports as `local:remote` or a single port, every port of the remote service when
+    /// empty
+    #[serde(default)]
+    #[schemars(schema_with = "ports")]
     pub ports: Vec<String>,
-    pub kube_config: KubeConfigReference,
+    /// Name of a `RemoteCluster` in the same namespace, instead of inline connection
+    /// settings
+    pub cluster_ref: Option<String>,
+    #[serde(flatten)]
+    pub connection: ClusterConnection,
+    /// A pooled forwarder is rolled when any of its members asks for `Restart`
+    pub credential_reload: Option<CredentialReload>,
+    /// Served by a forwarder shared with the other pooled `ForwardedService`s of the
+    /// namespace that mount the same credentials. Forwards within the local cluster are
+    /// never pooled.
+    pub pooled: Option<bool>,
+    /// For a `StatefulSet` target, also a local service `<name>-<ordinal>` per remote
+    /// pod, following the replicas of the stateful set. Such forwards are not pooled.
+    pub ordinal_services: Option<bool>,
+    /// Clusters the forwarder moves new connections to while the cluster above is
+    /// unhealthy. Such forwards are not pooled.
+    pub failover: Option<Failover>,
+    /// Spreads new connections over several clusters by weight, instead of `failover`.
+    /// Such forwards are not pooled.
+    pub traffic_split: Option<TrafficSplit>,
+    /// Settings merged into the generated local service
+    pub service_template: Option<ServiceTemplate>,
+    /// Settings merged into the pods of the dedicated forwarder. Such forwards are not
+    /// pooled.
+    pub pod_template: Option<PodTemplate>,
+    /// Pods of the dedicated forwarder, 1 by default. Several are spread over nodes and
+    /// zones, with a `PodDisruptionBudget` letting only one at a time be evicted. Such
+    /// forwards are not pooled.
+    pub replicas: Option<i32>,
+    /// Scales the forwarder to zero while keeping the local service, until set back to
+    /// `false`
+    pub suspend: Option<bool>,
+    /// Windows the forwarder runs in, scaled to zero outside of them with the local
+    /// service kept
+    pub schedule: Option<Schedule>,
+    /// Lifetime of a temporary forward
+    pub expiry: Option<Expiry>,
+}
+
+/// Local service settings. Labels and annotations set by the controller take precedence.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct ServiceTemplate {
+    /// Name of the service, that of the `ForwardedService` by default. Either has to be a
+    /// DNS-1035 label.
+    #[schemars(length(max = 63), regex(pattern = r"^[a-z]([-a-z0-9]*[a-z0-9])?$"))]
+    pub name: Option<String>,
+    /// Defaults to `ClusterIP`
+    #[serde(rename = "type")]
+    pub type_: Option<ServiceType>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Such as external-dns or cloud load balancer annotations
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    /// Client ranges allowed through a `LoadBalancer` service
+    pub load_balancer_source_ranges: Option<Vec<String>>,
+    pub ip_family_policy: Option<IpFamilyPolicy>,
+}
+
+/// When a forward ends, the earlier of `ttl_seconds` and `expires_at`
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct Expiry {
+    /// Seconds after creation
+    pub ttl_seconds: Option<u64>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Defaults to `Delete`
+    pub action: Option<ExpiryAction>,
+    /// Seconds beforehand a warning event is emitted, 3600 by default
+    pub warning_seconds: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum ExpiryAction {
+    /// Deletes the `ForwardedService`
+    #[default]
+    Delete,
+    /// Sets `suspend`, keeping the `ForwardedService` to be extended later
+    Suspend,
+}
+
+/// When a forward is active
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct Schedule {
+    /// The forward is active while any of them is open
+    pub windows: Vec<ScheduleWindow>,
+    /// IANA time zone the windows start in, such as `Europe/Berlin`. Defaults to UTC.
+    pub time_zone: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct ScheduleWindow {
+    /// Cron expression of the openings, such as `0 8 * * Mon-Fri`
+    pub start: String,
+    /// How long the window stays open after each start
+    pub duration_minutes: u32,
+}
+
+/// Forwarder pod settings. The container arguments and the credential volumes stay
+/// under the controller's control, and so do the labels, annotations and environment
+/// variables it sets.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct PodTemplate {
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    /// Requests and limits of the forwarder container
+    pub resources: Option<ResourceRequirements>,
+    /// Added to the forwarder container's environment
+    #[serde(default)]
+    pub env: Vec<EnvVar>,
+    #[serde(default)]
+    pub node_selector: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tolerations: Vec<Toleration>,
+    /// Pod affinity terms are added to those spreading several replicas
+    pub affinity: Option<Affinity>,
+    pub priority_class_name: Option<String>,
+    #[serde(default)]
+    pub image_pull_secrets: Vec<LocalObjectReference>,
+    /// Ignored for in-cluster credentials, which need the service account the controller
+    /// grants access to the targets
+    pub service_account_name: Option<String>,
+    /// Hardened defaults of the forwarder pod, each of which can be turned off
+    pub security: Option<PodSecurity>,
+}
+
+/// Security settings of the forwarder, all enabled unless set to `false`
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct PodSecurity {
+    /// Runs the forwarder as user 65534 and refuses to run as root
+    pub run_as_non_root: Option<bool>,
+    pub read_only_root_filesystem: Option<bool>,
+    /// Keeps the forwarder from gaining more privileges than it started with
+    pub disallow_privilege_escalation: Option<bool>,
+    /// Drops all Linux capabilities of the forwarder container
+    pub drop_all_capabilities: Option<bool>,
+    /// Applies the container runtime's default seccomp profile
+    pub seccomp_runtime_default: Option<bool>,
+    /// Leaves the service account token unmounted unless the forwarder uses in-cluster
+    /// credentials. Setting it to `false` mounts the token regardless.
+    pub disable_service_account_token: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum ServiceType {
+    #[default]
+    ClusterIP,
+    NodePort,
+    LoadBalancer,
+    /// A `ClusterIP` service without a cluster IP, resolving to the forwarder pods
+    Headless,
+}
+
+/// Passed on as the service's `ipFamilyPolicy`
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[allow(clippy::enum_variant_names)]
+pub enum IpFamilyPolicy {
+    SingleStack,
+    PreferDualStack,
+    RequireDualStack,
+}
+
+/// Weights of the clusters sharing new connections. Changing them does not restart the
+/// forwarder.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct TrafficSplit {
+    /// Weight of the cluster of the spec, relative to the other weights
+    pub weight: u32,
+    pub clusters: Vec<WeightedCluster>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct WeightedCluster {
+    /// `RemoteCluster` in the same namespace
+    pub cluster_ref: String,
+    pub weight: u32,
+}
+
+/// Ordered fallback clusters and the health checks switching between them
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct Failover {
+    /// `RemoteCluster`s in the same namespace, in order of preference after the cluster
+    /// of the spec
+    pub cluster_refs: Vec<String>,
+    /// Failed health checks in a row before the active cluster is left, 3 by default
+    pub failure_threshold: Option<u32>,
+    /// Passed health checks in a row before a preferred cluster is returned to, 5 by
+    /// default
+    pub success_threshold: Option<u32>,
+    /// Seconds between health checks, 10 by default
+    pub period_seconds: Option<u32>,
+}
+
+/// Remote pods a forwarder connects to
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct Target {
+    /// Defaults to `Service`
+    #[serde(default)]
+    pub kind: TargetKind,
+    /// Name of the object, unused for `Selector` and `Leader`
+    pub name: Option<String>,
+    /// Pod labels, only used for `Selector` and `Leader`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Seconds connections to a former `Leader` pod are kept open before they are
+    /// closed, 30 by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drain_seconds: Option<u32>,
+}
+
+/// Resolved to a ready pod for every connection
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum TargetKind {
+    /// A pod behind the service, with the service port mapped to the target port
+    #[default]
+    Service,
+    /// The named pod, ports are container ports
+    Pod,
+    /// A pod selected by the deployment
+    Deployment,
+    /// A pod selected by the stateful set
+    StatefulSet,
+    /// A pod carrying every label in `labels`
+    Selector,
+    /// The single ready pod carrying every label in `labels`, such as the primary of a
+    /// replicated database. New connections follow the labels when they move.
+    Leader,
+}
+
+/// How to reach a cluster. Without `kube_config`, `credentials` or `token_request` the
+/// local cluster is used with a controller-managed service account
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct ClusterConnection {
+    /// Remote cluster kubeconfig
+    pub kube_config: Option<KubeConfigReference>,
+    /// Connection settings read from individual keys, as an alternative to `kube_config`
+    pub credentials: Option<CredentialsReference>,
+    /// Short-lived tokens minted for a remote service account, as an alternative to
+    /// `kube_config` and `credentials`
+    pub token_request: Option<TokenRequestReference>,
+}
+
+/// Connection settings shared by the `ForwardedService`s that reference it
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(test, derive(Default))]
+#[kube(
+    kind = "RemoteCluster",
+    group = "port-forward-operator.rs",
+    version = "v1",
+    namespaced
+)]
+#[kube(status = "RemoteClusterStatus", shortname = "rc")]
+pub struct RemoteClusterSpec {
+    #[serde(flatten)]
+    pub connection: ClusterConnection,
+}
+
+/// The status object of `RemoteCluster`
+#[derive(Deserialize, Serialize, Clone, Default, Debug, JsonSchema)]
+pub struct RemoteClusterStatus {
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// Earliest expiry of the remote client certificate or token
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Expiry of the minted token
+    pub token_expires_at: Option<DateTime<Utc>>,
+    /// Content hash of the credentials, dependents are reconciled when it changes
+    pub credentials_hash: Option<String>,
+    /// Version reported by the remote API server
+    pub server_version: Option<String>,
+}
+
+/// Keeps a `ForwardedService` for every remote service matching a selector
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(test, derive(Default))]
+#[kube(
+    kind = "ServiceMirror",
+    group = "port-forward-operator.rs",
+    version = "v1",
+    namespaced
+)]
+#[kube(status = "ServiceMirrorStatus", shortname = "smir")]
+pub struct ServiceMirrorSpec {
+    /// Name of a `RemoteCluster` in the same namespace, the local cluster when absent
+    pub cluster_ref: Option<String>,
+    /// Namespace of the remote services, defaults to the namespace of the mirror. Within
+    /// the local cluster, another namespace has to opt in as for a `ForwardedService`.
+    pub namespace: Option<String>,
+    /// Labels a remote service needs to be mirrored, every service of the namespace
+    /// when empty
+    #[serde(default)]
+    pub selector: BTreeMap<String, String>,
+    /// Passed on to the mirrored `ForwardedService`s
+    pub pooled: Option<bool>,
+}
+
+/// The status object of `ServiceMirror`
+#[derive(Deserialize, Serialize, Clone, Default, Debug, JsonSchema)]
+pub struct ServiceMirrorStatus {
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// `ForwardedService`s created for the matching remote services
+    #[serde(default)]
+    pub forwarded_services: Vec<String>,
 }
 
 fn ports(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
@@ -37,20 +362,148 @@ fn ports(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
     .unwrap()
 }
 
+/// A kubeconfig in a secret. Only inline tokens and certificate data are accepted, no
+/// `exec`, `auth-provider` or file paths.
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
 pub struct KubeConfigReference {
     pub secret: String,
+    /// Namespace of the secret, which needs a `SecretReferenceGrant` there when it differs
+    /// from the namespace of the referencing object
+    pub namespace: Option<String>,
     pub key: Option<String>,
     pub context: String,
     pub user: Option<String>,
     pub cluster: Option<String>,
 }
 
+/// Allows objects in other namespaces to reference secrets in this namespace, which the
+/// controller then copies next to them
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "SecretReferenceGrant",
+    group = "port-forward-operator.rs",
+    version = "v1",
+    namespaced
+)]
+#[kube(shortname = "srg")]
+pub struct SecretReferenceGrantSpec {
+    #[schemars(length(min = 1))]
+    pub from: Vec<SecretReferenceGrantFrom>,
+    #[schemars(length(min = 1))]
+    pub to: Vec<SecretReferenceGrantTo>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct SecretReferenceGrantFrom {
+    /// Namespace of the referencing `ForwardedService` or `RemoteCluster`
+    pub namespace: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct SecretReferenceGrantTo {
+    /// Secret that may be referenced, every secret of the namespace when absent
+    pub name: Option<String>,
+}
+
+/// Remote cluster connection settings stored under separate keys
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct CredentialsReference {
+    /// Secret holding the token or client certificate and key
+    pub secret: String,
+    /// ConfigMap holding the server url and CA bundle, the secret is used when absent
+    pub config_map: Option<String>,
+    /// Defaults to `server`
+    pub server_key: Option<String>,
+    /// Defaults to `ca.crt`
+    pub certificate_authority_key: Option<String>,
+    /// Defaults to `token`
+    pub token_key: Option<String>,
+    /// Defaults to `tls.crt`
+    pub client_certificate_key: Option<String>,
+    /// Defaults to `tls.key`
+    pub client_key_key: Option<String>,
+}
+
+/// Mints audience-bound tokens for a remote service account with the TokenRequest API
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct TokenRequestReference {
+    /// Credentials the controller uses to mint tokens, never mounted into the forwarder
+    pub bootstrap: BootstrapCredentials,
+    pub service_account: String,
+    /// Namespace of the remote service account, defaults to the target namespace or the
+    /// namespace of the `RemoteCluster`
+    pub service_account_namespace: Option<String>,
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    /// Requested token lifetime, defaults to one hour
+    pub expiration_seconds: Option<i64>,
+}
+
+/// Exactly one of `kube_config` or `credentials`
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct BootstrapCredentials {
+    pub kube_config: Option<KubeConfigReference>,
+    pub credentials: Option<CredentialsReference>,
+}
+
+/// How a change to the credentials secret reaches a running forwarder, `Restart` unless
+/// set
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum CredentialReload {
+    /// The forwarder reloads the mounted credentials and keeps open connections
+    InPlace,
+    /// The forwarder pods are rolled
+    #[default]
+    Restart,
+}
+
 /// The status object of `ForwardedService`
 #[derive(Deserialize, Serialize, Clone, Default, Debug, JsonSchema)]
 pub struct ForwardedServiceStatus {
     pub service_name: String,
     pub pod_name: String,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// Earliest expiry of the remote client certificate or token
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Expiry of the minted token
+    pub token_expires_at: Option<DateTime<Utc>>,
+    /// Forwarder pool serving this service
+    pub pool: Option<String>,
+    /// Forwarded ports, read from the remote service when the spec lists none
+    #[serde(default)]
+    pub ports: Vec<ForwardedPort>,
+    /// Number of per-ordinal local services
+    pub ordinals: Option<i32>,
+    /// Cluster the forwarder currently forwards new connections through
+    pub active_cluster: Option<String>,
+    /// Failovers since the forwarder started
+    pub failovers: Option<u64>,
+    /// When the schedule next opens or closes a window
+    pub next_transition: Option<DateTime<Utc>>,
+    /// When a forward with an expiry is suspended
+    pub lifetime_ends_at: Option<DateTime<Utc>>,
+}
+
+/// A port of the local service and the remote port it forwards to
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct ForwardedPort {
+    pub name: String,
+    pub port: u16,
+    pub remote_port: u16,
+    pub app_protocol: Option<String>,
+}
+
+/// An observation about the state of a resource
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Condition {
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// One of `True`, `False` or `Unknown`
+    pub status: String,
+    pub reason: Option<String>,
+    pub message: Option<String>,
+    pub last_transition_time: Option<DateTime<Utc>>,
 }
 
 impl KubeConfigReference {
@@ -60,7 +513,224 @@ impl KubeConfigReference {
     }
 }
 
+impl CredentialsReference {
+    #[allow(dead_code)]
+    pub(crate) fn server_key_any(&self) -> String {
+        self.server_key.clone().unwrap_or("server".to_owned())
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn certificate_authority_key_any(&self) -> String {
+        self.certificate_authority_key
+            .clone()
+            .unwrap_or("ca.crt".to_owned())
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn token_key_any(&self) -> String {
+        self.token_key.clone().unwrap_or("token".to_owned())
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn client_certificate_key_any(&self) -> String {
+        self.client_certificate_key
+            .clone()
+            .unwrap_or("tls.crt".to_owned())
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn client_key_key_any(&self) -> String {
+        self.client_key_key.clone().unwrap_or("tls.key".to_owned())
+    }
+}
+
+impl Target {
+    /// Checks that the fields the kind needs are set
+    #[allow(dead_code)]
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match (self.kind, &self.name) {
+            (kind @ (TargetKind::Selector | TargetKind::Leader), _) if self.labels.is_empty() => {
+                Err(format!("a `{kind:?}` target needs labels"))
+            }
+            (TargetKind::Selector | TargetKind::Leader, _) => Ok(()),
+            (kind, None) => Err(format!("a `{kind:?}` target needs a name")),
+            (_, Some(name)) if name.is_empty() => Err("target name is empty".to_owned()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Label selector of a `Selector` or `Leader` target
+    #[allow(dead_code)]
+    pub(crate) fn selector(&self) -> String {
+        self.labels
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            TargetKind::Selector => write!(f, "pods {}", self.selector()),
+            TargetKind::Leader => write!(f, "leader of pods {}", self.selector()),
+            kind => write!(
+                f,
+                "{} {}",
+                format!("{kind:?}").to_lowercase(),
+                self.name.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl std::str::FromStr for TargetKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "service" | "svc" => Ok(Self::Service),
+            "pod" => Ok(Self::Pod),
+            "deployment" | "deploy" => Ok(Self::Deployment),
+            "statefulset" | "sts" => Ok(Self::StatefulSet),
+            "selector" => Ok(Self::Selector),
+            "leader" => Ok(Self::Leader),
+            _ => Err(format!("unknown target kind `{s}`")),
+        }
+    }
+}
+
+impl ClusterConnection {
+    #[allow(dead_code)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.kube_config.is_none() && self.credentials.is_none() && self.token_request.is_none()
+    }
+}
+
 impl ForwardedService {
+    /// Whether a shared forwarder serves this service
+    #[allow(dead_code)]
+    pub(crate) fn is_pooled(&self) -> bool {
+        self.spec.pooled.unwrap_or_default() && self.unpoolable_settings().is_empty()
+    }
+
+    /// Settings of the spec only a dedicated forwarder honours, so they keep the service
+    /// out of a pool
+    #[allow(dead_code)]
+    pub(crate) fn unpoolable_settings(&self) -> Vec<&'static str> {
+        [
+            ("ordinal_services", self.has_ordinal_services()),
+            ("failover", self.spec.failover.is_some()),
+            ("traffic_split", self.spec.traffic_split.is_some()),
+            ("pod_template", self.spec.pod_template.is_some()),
+            ("replicas", self.spec.replicas.is_some_and(|r| r != 1)),
+        ]
+        .into_iter()
+        .filter_map(|(setting, set)| set.then_some(setting))
+        .collect()
+    }
+
+    /// Whether every pod of a stateful set target gets its own local service
+    #[allow(dead_code)]
+    pub(crate) fn has_ordinal_services(&self) -> bool {
+        self.spec.ordinal_services.unwrap_or_default()
+            && self.target().kind == TargetKind::StatefulSet
+    }
+
+    /// Whether forwarding is turned off for now
+    #[allow(dead_code)]
+    pub(crate) fn is_suspended(&self) -> bool {
+        self.spec.suspend.unwrap_or_default()
+    }
+
+    /// End of the lifetime set by the expiry
+    #[allow(dead_code)]
+    pub(crate) fn lifetime_end(&self) -> Option<DateTime<Utc>> {
+        let expiry = self.spec.expiry.as_ref()?;
+        let ttl_end = expiry.ttl_seconds.and_then(|ttl| {
+            let created = self.metadata.creation_timestamp.as_ref()?.0;
+            let ttl = chrono::Duration::seconds(i64::try_from(ttl).ok()?);
+            created.checked_add_signed(ttl)
+        });
+        match (ttl_end, expiry.expires_at) {
+            (Some(ttl_end), Some(expires_at)) => Some(ttl_end.min(expires_at)),
+            (ttl_end, expires_at) => ttl_end.or(expires_at),
+        }
+    }
+
+    /// Pods of the dedicated forwarder, none while suspended
+    #[allow(dead_code)]
+    pub(crate) fn replicas(&self) -> i32 {
+        match self.is_suspended() {
+            true => 0,
+            false => self.spec.replicas.unwrap_or(1).max(0),
+        }
+    }
+
+    /// Name of the generated local service
+    #[allow(dead_code)]
+    pub(crate) fn service_name(&self) -> String {
+        self.spec
+            .service_template
+            .as_ref()
+            .and_then(|t| t.name.clone())
+            .unwrap_or_else(|| self.name_any())
+    }
+
+    /// Checks that the local service name is a DNS-1035 label, which the name of the
+    /// `ForwardedService` need not be
+    #[allow(dead_code)]
+    pub(crate) fn validate_service_name(&self) -> Result<(), String> {
+        let name = self.service_name();
+        let mut chars = name.chars();
+        let valid = name.len() <= 63
+            && chars.next().is_some_and(|c| c.is_ascii_lowercase())
+            && !name.ends_with('-')
+            && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        match valid {
+            true => Ok(()),
+            false => Err(format!(
+                "service name `{name}` is not a DNS-1035 label of at most 63 characters"
+            )),
+        }
+    }
+
+    /// The explicit target, or the service named in the spec
+    #[allow(dead_code)]
+    pub(crate) fn target(&self) -> Target {
+        self.spec.target.clone().unwrap_or_else(|| Target {
+            kind: TargetKind::Service,
+            name: Some(self.spec.service.clone()),
+            ..Default::default()
+        })
+    }
+
+    /// Ports listed in the spec, or those resolved from the remote service
+    #[allow(dead_code)]
+    pub(crate) fn forwarded_ports(&self) -> Vec<ForwardedPort> {
+        if self.spec.ports.is_empty() {
+            return self
+                .status
+                .as_ref()
+                .map(|s| s.ports.clone())
+                .unwrap_or_default();
+        }
+        self.spec
+            .ports
+            .iter()
+            .filter_map(|port| {
+                let (local, remote) = port.split_once(':').unwrap_or((port, port));
+                Some(ForwardedPort {
+                    name: port.replace(':', "-"),
+                    port: local.parse().ok()?,
+                    remote_port: remote.parse().ok()?,
+                    app_protocol: None,
+                })
+            })
+            .collect()
+    }
+
     #[allow(dead_code)]
     pub(crate) fn annotate(&self) -> BTreeMap<String, String> {
         let mut annotations: BTreeMap<String, String> = BTreeMap::new();
@@ -76,6 +746,7 @@ impl Default for KubeConfigReference {
     fn default() -> Self {
         Self {
             secret: Default::default(),
+            namespace: None,
             key: Some("config".to_owned()),
             context: String::new(),
             user: None,
@@ -86,12 +757,168 @@ impl Default for KubeConfigReference {
 
 #[cfg(test)]
 mod tests {
-    use super::KubeConfigReference;
+    use std::collections::BTreeMap;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+
+    use super::{
+        Expiry, ForwardedPort, ForwardedService, ForwardedServiceSpec, KubeConfigReference,
+        PodTemplate, ServiceTemplate, Target, TargetKind,
+    };
+
+    #[test]
+    fn test_target_validate_by_kind() {
+        let target = |kind, name: Option<&str>, labels: &[(&str, &str)]| Target {
+            kind,
+            name: name.map(str::to_owned),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+            ..Default::default()
+        };
+        assert!(target(TargetKind::Pod, Some("db-0"), &[])
+            .validate()
+            .is_ok());
+        assert!(target(TargetKind::StatefulSet, None, &[])
+            .validate()
+            .is_err());
+        assert!(target(TargetKind::Selector, None, &[]).validate().is_err());
+        let selector = target(
+            TargetKind::Selector,
+            None,
+            &[("app", "db"), ("role", "primary")],
+        );
+        assert!(selector.validate().is_ok());
+        assert_eq!("pods app=db,role=primary", selector.to_string());
+        assert!(target(TargetKind::Leader, Some("db"), &[])
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_forwarded_ports_from_spec() {
+        let fs = ForwardedService::new(
+            "postgres",
+            ForwardedServiceSpec {
+                service: "postgres".to_owned(),
+                ports: vec!["15432:5432".to_owned(), "8080".to_owned()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            vec![
+                ForwardedPort {
+                    name: "15432-5432".to_owned(),
+                    port: 15432,
+                    remote_port: 5432,
+                    app_protocol: None,
+                },
+                ForwardedPort {
+                    name: "8080".to_owned(),
+                    port: 8080,
+                    remote_port: 8080,
+                    app_protocol: None,
+                },
+            ],
+            fs.forwarded_ports()
+        );
+    }
+
+    #[test]
+    fn test_lifetime_ends_at_earliest_expiry() {
+        let created = Utc.with_ymd_and_hms(2024, 1, 8, 9, 0, 0).unwrap();
+        let mut fs = ForwardedService::new("postgres", ForwardedServiceSpec::default());
+        fs.metadata.creation_timestamp = Some(Time(created));
+        assert_eq!(None, fs.lifetime_end());
+        fs.spec.expiry = Some(Expiry {
+            ttl_seconds: Some(3600),
+            ..Default::default()
+        });
+        assert_eq!(Some(created + Duration::hours(1)), fs.lifetime_end());
+        fs.spec.expiry.as_mut().unwrap().expires_at = Some(created + Duration::minutes(30));
+        assert_eq!(Some(created + Duration::minutes(30)), fs.lifetime_end());
+    }
+
+    #[test]
+    fn test_suspend_scales_to_zero() {
+        let mut fs = ForwardedService::new(
+            "postgres",
+            ForwardedServiceSpec {
+                replicas: Some(3),
+                ..Default::default()
+            },
+        );
+        assert_eq!(3, fs.replicas());
+        fs.spec.suspend = Some(true);
+        assert_eq!(0, fs.replicas());
+    }
+
+    #[test]
+    fn test_dedicated_settings_keep_forwards_out_of_pools() {
+        let mut fs = ForwardedService::new(
+            "postgres",
+            ForwardedServiceSpec {
+                pooled: Some(true),
+                replicas: Some(1),
+                ..Default::default()
+            },
+        );
+        assert!(fs.is_pooled());
+        fs.spec.replicas = Some(2);
+        fs.spec.pod_template = Some(PodTemplate::default());
+        assert_eq!(vec!["pod_template", "replicas"], fs.unpoolable_settings());
+        assert!(!fs.is_pooled());
+    }
+
+    #[test]
+    fn test_service_name_override() {
+        let mut fs = ForwardedService::new(
+            "postgres",
+            ForwardedServiceSpec {
+                service: "postgres".to_owned(),
+                ..Default::default()
+            },
+        );
+        assert_eq!("postgres", fs.service_name());
+        fs.spec.service_template = Some(ServiceTemplate {
+            name: Some("db".to_owned()),
+            ..Default::default()
+        });
+        assert_eq!("db", fs.service_name());
+    }
+
+    #[test]
+    fn test_service_name_is_a_dns_label() {
+        let named = |name: &str| {
+            ForwardedService::new(
+                "postgres.team",
+                ForwardedServiceSpec {
+                    service_template: Some(ServiceTemplate {
+                        name: Some(name.to_owned()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )
+        };
+        assert!(named("db-1").validate_service_name().is_ok());
+        assert!(named("1db").validate_service_name().is_err());
+        assert!(named("db-").validate_service_name().is_err());
+        assert!(named("Db").validate_service_name().is_err());
+        assert!(named(&"d".repeat(64)).validate_service_name().is_err());
+        // the default, the name of the forwarded service, may contain dots
+        let mut fs = named("db");
+        fs.spec.service_template = None;
+        assert!(fs.validate_service_name().is_err());
+    }
 
     #[test]
     fn test_key_any_on_set_key() {
         let reference = KubeConfigReference {
             secret: "secret".to_owned(),
+            namespace: None,
             key: Some("key".to_owned()),
             context: "context".to_owned(),
             user: None,
@@ -106,6 +933,7 @@ mod tests {
     fn test_key_any_on_no_key() {
         let reference = KubeConfigReference {
             secret: "secret".to_owned(),
+            namespace: None,
             key: None,
             context: "context".to_owned(),
             user: None,
@@ -1,8 +1,19 @@
 use kube::CustomResourceExt;
 mod crd;
+
+/// Prints every CRD, or only the one whose kind is given as the first argument
 fn main() {
-    print!(
-        "{}",
-        serde_yaml::to_string(&crd::ForwardedService::crd()).unwrap()
-    )
+    let kind = std::env::args().nth(1);
+    let crds = [
+        crd::ForwardedService::crd(),
+        crd::RemoteCluster::crd(),
+        crd::SecretReferenceGrant::crd(),
+        crd::ServiceMirror::crd(),
+    ];
+    let documents = crds
+        .iter()
+        .filter(|crd| kind.is_none() || kind.as_deref() == Some(&crd.spec.names.kind))
+        .map(|crd| serde_yaml::to_string(crd).unwrap())
+        .collect::<Vec<_>>();
+    print!("{}", documents.join("---\n"))
 }
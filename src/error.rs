@@ -17,8 +17,14 @@ pub enum Error {
     MaxAttempts(i32),
     #[error("server error: {0}")]
     Server(String),
+    #[error("invalid port mapping `{0}`")]
+    InvalidPort(String),
+    #[error("invalid credentials: {0}")]
+    Credentials(String),
+    #[error("tls error: {0}")]
+    Tls(String),
     #[error("finalizer error: {0}")]
-    FinalizerError(#[source] Box<kube::runtime::finalizer::Error<Error>>),
+    Finalizer(#[source] Box<kube::runtime::finalizer::Error<Error>>),
     #[error("service `{name}` error: {message}")]
     InvalidService { name: String, message: String },
 }
@@ -0,0 +1,140 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use kube::config::{
+    AuthInfo, Cluster, Context, Kubeconfig, NamedAuthInfo, NamedCluster, NamedContext,
+};
+use secrecy::SecretString;
+
+/// Name of the cluster, user and context in an assembled kubeconfig
+pub(crate) const REMOTE_CONTEXT: &str = "remote";
+
+/// Connection settings for a remote cluster kept as individual values
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ClusterCredentials {
+    pub server: Option<String>,
+    /// PEM encoded CA bundle, the system roots are used when absent
+    pub certificate_authority: Option<String>,
+    pub token: Option<String>,
+    /// PEM encoded client certificate
+    pub client_certificate: Option<String>,
+    /// PEM encoded client key
+    pub client_key: Option<String>,
+}
+
+impl ClusterCredentials {
+    pub fn validate(&self) -> Result<(), String> {
+        if self
+            .server
+            .as_deref()
+            .map(str::trim)
+            .unwrap_or_default()
+            .is_empty()
+        {
+            return Err("no server url".to_owned());
+        }
+
+        match (&self.client_certificate, &self.client_key) {
+            (Some(_), None) => Err("client certificate without a client key".to_owned()),
+            (None, Some(_)) => Err("client key without a client certificate".to_owned()),
+            (None, None) if self.token.is_none() => {
+                Err("neither a token nor a client certificate".to_owned())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Builds a single-context kubeconfig named [`REMOTE_CONTEXT`]
+    pub fn to_kubeconfig(&self) -> Kubeconfig {
+        let encode = |pem: &String| STANDARD.encode(pem.as_bytes());
+        Kubeconfig {
+            clusters: vec![NamedCluster {
+                name: REMOTE_CONTEXT.to_owned(),
+                cluster: Some(Cluster {
+                    server: self.server.as_ref().map(|s| s.trim().to_owned()),
+                    certificate_authority_data: self.certificate_authority.as_ref().map(encode),
+                    ..Default::default()
+                }),
+            }],
+            auth_infos: vec![NamedAuthInfo {
+                name: REMOTE_CONTEXT.to_owned(),
+                auth_info: Some(AuthInfo {
+                    token: self
+                        .token
+                        .as_ref()
+                        .map(|t| SecretString::new(t.trim().to_owned())),
+                    client_certificate_data: self.client_certificate.as_ref().map(encode),
+                    client_key_data: self
+                        .client_key
+                        .as_ref()
+                        .map(|k| SecretString::new(encode(k))),
+                    ..Default::default()
+                }),
+            }],
+            contexts: vec![NamedContext {
+                name: REMOTE_CONTEXT.to_owned(),
+                context: Some(Context {
+                    cluster: REMOTE_CONTEXT.to_owned(),
+                    user: REMOTE_CONTEXT.to_owned(),
+                    namespace: None,
+                    extensions: None,
+                }),
+            }],
+            current_context: Some(REMOTE_CONTEXT.to_owned()),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::ExposeSecret;
+
+    use super::{ClusterCredentials, REMOTE_CONTEXT};
+
+    fn token_credentials() -> ClusterCredentials {
+        ClusterCredentials {
+            server: Some("https://remote.example.com\n".to_owned()),
+            token: Some("abcdef\n".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_token_credentials() {
+        assert!(token_credentials().validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_missing_server() {
+        let credentials = ClusterCredentials {
+            server: None,
+            ..token_credentials()
+        };
+        assert_eq!("no server url", credentials.validate().unwrap_err());
+    }
+
+    #[test]
+    fn test_validate_certificate_without_key() {
+        let credentials = ClusterCredentials {
+            token: None,
+            client_certificate: Some("cert".to_owned()),
+            ..token_credentials()
+        };
+        assert!(credentials.validate().is_err());
+    }
+
+    #[test]
+    fn test_to_kubeconfig_trims_values() {
+        let kubeconfig = token_credentials().to_kubeconfig();
+        assert_eq!(Some(REMOTE_CONTEXT.to_owned()), kubeconfig.current_context);
+        let cluster = kubeconfig.clusters[0].cluster.as_ref().unwrap();
+        assert_eq!(
+            Some("https://remote.example.com"),
+            cluster.server.as_deref()
+        );
+        let auth_info = kubeconfig.auth_infos[0].auth_info.as_ref().unwrap();
+        assert_eq!(
+            "abcdef",
+            auth_info.token.as_ref().unwrap().expose_secret().as_str()
+        );
+    }
+}
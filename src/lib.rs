@@ -1,28 +1,117 @@
 mod controller;
 mod crd;
 mod error;
+mod kubeconfig;
 mod service;
 
+pub use service::CredentialFiles;
+
 type Result<T> = std::result::Result<T, error::Error>;
 
-pub async fn start_controller(image: String, listen_address: String) -> Result<()> {
+pub async fn start_controller(
+    image: String,
+    listen_address: String,
+    webhook_listen_address: String,
+    webhook_service: String,
+    webhook_secret: String,
+) -> Result<()> {
     let b = Box::new(listen_address);
     let jh = tokio::spawn(controller::host::start_host(Box::leak(b)));
+    let namespace = kube::Client::try_default()
+        .await
+        .map_err(|e| error::Error::KubeClient { source: e })?
+        .default_namespace()
+        .to_owned();
+    let webhook = tokio::spawn(async move {
+        let options = controller::tls::TlsOptions {
+            secret: webhook_secret,
+            service: webhook_service,
+            namespace,
+        };
+        if let Err(e) = controller::host::start_webhook_host(&webhook_listen_address, options).await
+        {
+            tracing::error!("webhook server exited: {}", e);
+        }
+    });
     controller::start(controller::new_state(image)).await;
+    webhook.abort();
     jh.await.unwrap()
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start_service(
-    namespace: String,
-    name: String,
-    ports: Vec<u16>,
+    namespace: Option<String>,
+    kind: String,
+    name: Option<String>,
+    selector: Option<String>,
+    drain_seconds: Option<u32>,
+    ports: Vec<String>,
+    config: Option<String>,
     max_retries: Option<i32>,
+    kube_config_path: Option<String>,
     kube_config: kube::config::KubeConfigOptions,
+    credential_files: Option<CredentialFiles>,
+    failover: Option<String>,
+    split: Option<String>,
+    reload_interval: std::time::Duration,
+    metrics_address: String,
 ) -> Result<()> {
     tracing_subscriber::fmt::init();
+    let targets = match (config, namespace) {
+        (Some(path), _) => service::TargetSource::File(path),
+        (None, Some(namespace)) => {
+            let target = crd::Target {
+                kind: kind.parse().map_err(error::Error::Server)?,
+                name,
+                labels: selector
+                    .iter()
+                    .flat_map(|s| s.split(','))
+                    .filter_map(|label| label.split_once('='))
+                    .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
+                    .collect(),
+                drain_seconds,
+            };
+            target.validate().map_err(error::Error::Server)?;
+            service::TargetSource::Static(service::ForwarderConfig {
+                targets: vec![service::ForwardTarget {
+                    namespace,
+                    target,
+                    ports: ports.iter().map(|p| p.parse()).collect::<Result<_>>()?,
+                }],
+            })
+        }
+        _ => {
+            return Err(error::Error::Server(
+                "either a config file or a namespace and target are required".to_owned(),
+            ))
+        }
+    };
+    let failover = failover
+        .map(|failover| {
+            serde_json::from_str(&failover)
+                .map_err(|e| error::Error::Server(format!("invalid failover config: {e}")))
+        })
+        .transpose()?;
+    let split = split
+        .map(|split| {
+            serde_json::from_str(&split)
+                .map_err(|e| error::Error::Server(format!("invalid split config: {e}")))
+        })
+        .transpose()?;
     service::start(
-        service::ServiceOptions::new(namespace, name, ports, max_retries),
-        &kube_config,
+        targets,
+        max_retries,
+        match credential_files {
+            Some(files) => service::CredentialSource::Files(files),
+            None => service::CredentialSource::KubeConfig {
+                path: kube_config_path,
+                options: kube_config,
+            },
+        },
+        failover,
+        split,
+        reload_interval,
+        &metrics_address,
     )
     .await
 }
@@ -1,5 +1,5 @@
 use clap::Parser;
-use port_forward_operator::{start_controller, start_service};
+use port_forward_operator::{start_controller, start_service, CredentialFiles};
 mod app;
 
 #[tokio::main]
@@ -9,26 +9,69 @@ async fn main() {
         app::SubCommand::Controller {
             listen_address,
             image,
-        } => start_controller(image, listen_address).await,
+            webhook_listen_address,
+            webhook_service,
+            webhook_secret,
+        } => {
+            start_controller(
+                image,
+                listen_address,
+                webhook_listen_address,
+                webhook_service,
+                webhook_secret,
+            )
+            .await
+        }
         app::SubCommand::Service {
             namespace,
+            kind,
             name,
+            selector,
+            drain_seconds,
             ports,
+            config,
             max_retries,
             kube_context,
             kube_user,
             kube_cluster,
+            kubeconfig,
+            server_file,
+            certificate_authority_file,
+            token_file,
+            client_certificate_file,
+            client_key_file,
+            failover,
+            split,
+            reload_interval,
+            metrics_address,
         } => {
+            let credential_files = server_file.map(|server| CredentialFiles {
+                server,
+                certificate_authority: certificate_authority_file,
+                token: token_file,
+                client_certificate: client_certificate_file,
+                client_key: client_key_file,
+            });
             start_service(
                 namespace,
+                kind,
                 name,
+                selector,
+                drain_seconds,
                 ports,
+                config,
                 Some(max_retries),
+                kubeconfig,
                 kube::config::KubeConfigOptions {
-                    context: Some(kube_context),
+                    context: kube_context,
                     cluster: kube_cluster,
                     user: kube_user,
                 },
+                credential_files,
+                failover,
+                split,
+                std::time::Duration::from_secs(reload_interval),
+                metrics_address,
             )
             .await
         }
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use kube::{
+    config::{KubeConfigOptions, Kubeconfig},
+    Client, Config,
+};
+use tokio::sync::watch;
+
+use crate::{error::Error, kubeconfig::ClusterCredentials};
+
+/// Where the forwarder reads its remote cluster credentials from
+pub(crate) enum CredentialSource {
+    /// Kubeconfig file to watch, falls back to the default lookup when empty and to the
+    /// in-cluster service account when no context, cluster or user is named either
+    KubeConfig {
+        path: Option<String>,
+        options: KubeConfigOptions,
+    },
+    /// Individual files for the server url, CA bundle, token and client certificate
+    Files(CredentialFiles),
+}
+
+/// Paths of the individual credential files mounted into the forwarder
+pub struct CredentialFiles {
+    pub server: String,
+    pub certificate_authority: Option<String>,
+    pub token: Option<String>,
+    pub client_certificate: Option<String>,
+    pub client_key: Option<String>,
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|e| Error::Credentials(format!("unable to read {path}: {e}")))
+}
+
+/// Reads a file that is only present when the matching secret key exists
+fn read_optional(path: &Option<String>) -> Result<Option<String>, Error> {
+    match path {
+        Some(path) if std::path::Path::new(path).exists() => Ok(Some(
+            String::from_utf8_lossy(&read_file(path)?).into_owned(),
+        )),
+        _ => Ok(None),
+    }
+}
+
+impl CredentialFiles {
+    fn read(&self) -> Result<ClusterCredentials, Error> {
+        let credentials = ClusterCredentials {
+            server: Some(String::from_utf8_lossy(&read_file(&self.server)?).into_owned()),
+            certificate_authority: read_optional(&self.certificate_authority)?,
+            token: read_optional(&self.token)?,
+            client_certificate: read_optional(&self.client_certificate)?,
+            client_key: read_optional(&self.client_key)?,
+        };
+        credentials.validate().map_err(Error::Credentials)?;
+        Ok(credentials)
+    }
+}
+
+impl CredentialSource {
+    fn is_watched(&self) -> bool {
+        !matches!(self, Self::KubeConfig { path: None, .. })
+    }
+
+    /// Returns the kubeconfig contents, `None` when the default lookup applies
+    fn read(&self) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            Self::KubeConfig {
+                path: Some(path), ..
+            } => read_file(path).map(Some),
+            Self::KubeConfig { path: None, .. } => Ok(None),
+            Self::Files(files) => serde_yaml::to_string(&files.read()?.to_kubeconfig())
+                .map(|yaml| Some(yaml.into_bytes()))
+                .map_err(|e| Error::Credentials(e.to_string())),
+        }
+    }
+
+    async fn load(&self, contents: Option<&[u8]>) -> Result<Client, Error> {
+        let options = match self {
+            Self::KubeConfig { options, .. } => options.clone(),
+            Self::Files(_) => KubeConfigOptions::default(),
+        };
+        let config = match contents {
+            Some(contents) => {
+                let text =
+                    std::str::from_utf8(contents).map_err(|e| Error::Credentials(e.to_string()))?;
+                Config::from_custom_kubeconfig(Kubeconfig::from_yaml(text)?, &options).await?
+            }
+            None if options.context.is_none()
+                && options.cluster.is_none()
+                && options.user.is_none() =>
+            {
+                Config::infer()
+                    .await
+                    .map_err(|e| Error::Credentials(e.to_string()))?
+            }
+            None => Config::from_kubeconfig(&options).await?,
+        };
+        Client::try_from(config).map_err(|e| Error::KubeClient { source: e })
+    }
+}
+
+/// Hands out the current client and swaps it when the credentials change on disk.
+///
+/// Connections keep the client they started with, so in-flight streams finish on the
+/// old credentials while new streams pick up the reloaded ones.
+pub(crate) struct ReloadingClient {
+    receiver: watch::Receiver<Client>,
+}
+
+impl ReloadingClient {
+    pub async fn start(source: CredentialSource, interval: Duration) -> Result<Self, Error> {
+        let contents = source.read()?;
+        let client = source.load(contents.as_deref()).await?;
+        let (sender, receiver) = watch::channel(client);
+        if source.is_watched() {
+            tokio::spawn(watch_credentials(source, contents, sender, interval));
+        }
+        Ok(Self { receiver })
+    }
+
+    pub fn current(&self) -> Client {
+        self.receiver.borrow().clone()
+    }
+}
+
+async fn watch_credentials(
+    source: CredentialSource,
+    mut contents: Option<Vec<u8>>,
+    sender: watch::Sender<Client>,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let latest = match source.read() {
+            Ok(latest) => latest,
+            Err(e) => {
+                tracing::warn!("credential reload failed: {}", e);
+                metrics::increment_counter!("forwarder_credential_reload_failures_total");
+                continue;
+            }
+        };
+        if latest == contents {
+            continue;
+        }
+
+        match source.load(latest.as_deref()).await {
+            Ok(client) => {
+                tracing::info!("reloaded credentials");
+                metrics::increment_counter!("forwarder_credential_reloads_total");
+                contents = latest;
+                if sender.send(client).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                tracing::warn!("credential reload failed: {}", e);
+                metrics::increment_counter!("forwarder_credential_reload_failures_total");
+            }
+        }
+    }
+}
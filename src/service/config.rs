@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+use super::PortMapping;
+use crate::{crd::Target, error::Error};
+
+/// Services a forwarder serves. Pooled forwarders read it from a file the controller
+/// generates, a single service is given on the command line.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ForwarderConfig {
+    pub targets: Vec<ForwardTarget>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ForwardTarget {
+    pub namespace: String,
+    #[serde(flatten)]
+    pub target: Target,
+    pub ports: Vec<PortMapping>,
+}
+
+/// Where the forwarder reads its targets from
+pub(crate) enum TargetSource {
+    Static(ForwarderConfig),
+    /// JSON file that is watched for changes
+    File(String),
+}
+
+impl TargetSource {
+    pub fn read(&self) -> Result<ForwarderConfig, Error> {
+        match self {
+            Self::Static(config) => Ok(config.clone()),
+            Self::File(path) => {
+                let contents = std::fs::read(path)
+                    .map_err(|e| Error::Server(format!("unable to read {path}: {e}")))?;
+                serde_json::from_slice(&contents)
+                    .map_err(|e| Error::Server(format!("invalid forwarder config {path}: {e}")))
+            }
+        }
+    }
+
+    pub fn is_watched(&self) -> bool {
+        matches!(self, Self::File(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ForwardTarget, ForwarderConfig};
+    use crate::{
+        crd::{Target, TargetKind},
+        service::PortMapping,
+    };
+
+    #[test]
+    fn test_config_port_mappings_as_strings() {
+        let config: ForwarderConfig = serde_json::from_str(
+            r#"{"targets":[{"namespace":"db","name":"postgres","ports":["20000:5432"]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ForwarderConfig {
+                targets: vec![ForwardTarget {
+                    namespace: "db".to_owned(),
+                    target: Target {
+                        kind: TargetKind::Service,
+                        name: Some("postgres".to_owned()),
+                        ..Default::default()
+                    },
+                    ports: vec![PortMapping {
+                        local: 20000,
+                        remote: 5432
+                    }],
+                }]
+            },
+            config
+        );
+        assert_eq!(
+            r#"{"targets":[{"namespace":"db","kind":"Service","name":"postgres","ports":["20000:5432"]}]}"#,
+            serde_json::to_string(&config).unwrap()
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use kube::{config::KubeConfigOptions, Client};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use super::{
+    client::{CredentialSource, ReloadingClient},
+    resolve,
+    split::Split,
+    CredentialFiles, ForwarderConfig,
+};
+use crate::error::Error;
+
+/// Clusters the forwarder fails over to when the one of its own credentials is
+/// unhealthy, in order of preference
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FailoverConfig {
+    /// Name reported for the cluster of the forwarder's own credentials
+    pub primary: String,
+    pub clusters: Vec<FailoverCluster>,
+    /// Failed health checks in a row before the active cluster is left
+    pub failure_threshold: u32,
+    /// Passed health checks in a row before a preferred cluster is returned to
+    pub success_threshold: u32,
+    pub period_seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FailoverCluster {
+    pub name: String,
+    #[serde(flatten)]
+    pub credentials: CredentialPaths,
+}
+
+/// Where the credentials of a cluster are mounted, the same as the command line options
+/// of the forwarder
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct CredentialPaths {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kubeconfig: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kube_context: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kube_user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kube_cluster: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate_authority_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_certificate_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key_file: Option<String>,
+}
+
+impl CredentialPaths {
+    /// Appends the command line options selecting these credentials
+    pub fn args(&self, args: &mut Vec<String>) {
+        let options = [
+            ("--kubeconfig", &self.kubeconfig),
+            ("--kube-context", &self.kube_context),
+            ("--kube-user", &self.kube_user),
+            ("--kube-cluster", &self.kube_cluster),
+            ("--server-file", &self.server_file),
+            (
+                "--certificate-authority-file",
+                &self.certificate_authority_file,
+            ),
+            ("--token-file", &self.token_file),
+            ("--client-certificate-file", &self.client_certificate_file),
+            ("--client-key-file", &self.client_key_file),
+        ];
+        for (option, value) in options {
+            if let Some(value) = value {
+                args.push(option.to_owned());
+                args.push(value.clone());
+            }
+        }
+    }
+
+    fn source(&self) -> CredentialSource {
+        match &self.server_file {
+            Some(server) => CredentialSource::Files(CredentialFiles {
+                server: server.clone(),
+                certificate_authority: self.certificate_authority_file.clone(),
+                token: self.token_file.clone(),
+                client_certificate: self.client_certificate_file.clone(),
+                client_key: self.client_key_file.clone(),
+            }),
+            None => CredentialSource::KubeConfig {
+                path: self.kubeconfig.clone(),
+                options: KubeConfigOptions {
+                    context: self.kube_context.clone(),
+                    cluster: self.kube_cluster.clone(),
+                    user: self.kube_user.clone(),
+                },
+            },
+        }
+    }
+}
+
+/// What the forwarder reports about its clusters
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FailoverStatus {
+    /// Cluster new connections are forwarded through
+    pub active: String,
+    /// Times the active cluster was left for being unhealthy since the forwarder started
+    pub failovers: u64,
+}
+
+/// Health check history deciding the active cluster
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Health {
+    pub active: usize,
+    failures: u32,
+    successes: Vec<u32>,
+}
+
+impl Health {
+    pub fn new(clusters: usize) -> Self {
+        Self {
+            active: 0,
+            failures: 0,
+            successes: vec![0; clusters],
+        }
+    }
+
+    /// Records a round of health checks, one per cluster. Returns the cluster to switch
+    /// to and whether it is a failover, as opposed to a failback.
+    pub fn observe(
+        &mut self,
+        healthy: &[bool],
+        failure_threshold: u32,
+        success_threshold: u32,
+    ) -> Option<(usize, bool)> {
+        for (successes, healthy) in self.successes.iter_mut().zip(healthy) {
+            *successes = if *healthy { *successes + 1 } else { 0 };
+        }
+        match healthy[self.active] {
+            true => self.failures = 0,
+            false => self.failures += 1,
+        }
+        // back to a preferred cluster once it stayed healthy for long enough
+        if let Some(preferred) =
+            (0..self.active).find(|i| self.successes[*i] >= success_threshold.max(1))
+        {
+            self.active = preferred;
+            self.failures = 0;
+            return Some((preferred, false));
+        }
+        if self.failures < failure_threshold.max(1) {
+            return None;
+        }
+        let next = (0..healthy.len()).find(|i| *i != self.active && healthy[*i])?;
+        self.active = next;
+        self.failures = 0;
+        Some((next, true))
+    }
+}
+
+/// The clusters a forwarder can reach its targets in, handing out the one each new
+/// connection goes to
+pub(crate) struct Clusters {
+    clusters: Vec<(String, ReloadingClient)>,
+    active: watch::Sender<usize>,
+    failovers: AtomicU64,
+    /// Weights of a traffic split, which takes precedence over the active cluster
+    split: Mutex<Option<Split>>,
+}
+
+impl Clusters {
+    pub fn single(client: ReloadingClient) -> Self {
+        Self {
+            clusters: vec![("primary".to_owned(), client)],
+            active: watch::channel(0).0,
+            failovers: AtomicU64::default(),
+            split: Mutex::default(),
+        }
+    }
+
+    /// Loads the credentials of every other cluster, starting on the primary one
+    pub async fn start(
+        primary: ReloadingClient,
+        primary_name: &str,
+        others: &[FailoverCluster],
+        reload_interval: Duration,
+    ) -> Result<Self, Error> {
+        let mut clusters = vec![(primary_name.to_owned(), primary)];
+        for cluster in others {
+            let client =
+                ReloadingClient::start(cluster.credentials.source(), reload_interval).await?;
+            clusters.push((cluster.name.clone(), client));
+        }
+        Ok(Self {
+            clusters,
+            active: watch::channel(0).0,
+            failovers: AtomicU64::default(),
+            split: Mutex::default(),
+        })
+    }
+
+    /// Cluster of a new connection, by weight when traffic is split
+    pub fn pick(&self) -> usize {
+        let picked = self.split.lock().unwrap().as_mut().and_then(Split::next);
+        picked.unwrap_or_else(|| *self.active.borrow())
+    }
+
+    pub fn client(&self, cluster: usize) -> Client {
+        self.clusters[cluster].1.current()
+    }
+
+    pub fn name(&self, cluster: usize) -> &str {
+        &self.clusters[cluster].0
+    }
+
+    /// Splits new connections by the weights of the clusters named in `weights`, the
+    /// others get none
+    pub fn set_weights(&self, weights: &BTreeMap<String, u32>) {
+        let weights: Vec<u32> = self
+            .clusters
+            .iter()
+            .map(|(name, _)| weights.get(name).copied().unwrap_or_default())
+            .collect();
+        let mut split = self.split.lock().unwrap();
+        if split
+            .as_ref()
+            .is_none_or(|split| split.weights() != weights)
+        {
+            *split = Some(Split::new(weights));
+        }
+    }
+
+    pub fn status(&self) -> FailoverStatus {
+        FailoverStatus {
+            active: self.clusters[*self.active.borrow()].0.clone(),
+            failovers: self.failovers.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Checks every cluster each period and moves new connections to the most preferred
+    /// healthy one according to the thresholds
+    pub async fn monitor(
+        self: Arc<Self>,
+        config: FailoverConfig,
+        targets: watch::Receiver<ForwarderConfig>,
+    ) {
+        let period = Duration::from_secs(config.period_seconds.max(1));
+        let mut health = Health::new(self.clusters.len());
+        loop {
+            tokio::time::sleep(period).await;
+            let current = targets.borrow().clone();
+            let checks = self
+                .clusters
+                .iter()
+                .map(|(_, client)| healthy(client.current(), &current, period));
+            let healthy = futures::future::join_all(checks).await;
+            let Some((next, failover)) =
+                health.observe(&healthy, config.failure_threshold, config.success_threshold)
+            else {
+                continue;
+            };
+            let (from, to) = (
+                &self.clusters[*self.active.borrow()].0,
+                &self.clusters[next].0,
+            );
+            match failover {
+                true => {
+                    tracing::warn!("cluster {} is unhealthy, failing over to {}", from, to);
+                    self.failovers.fetch_add(1, Ordering::Relaxed);
+                    metrics::increment_counter!("forwarder_failovers_total");
+                }
+                false => tracing::info!("cluster {} is healthy again, leaving {}", to, from),
+            }
+            self.active.send_replace(next);
+        }
+    }
+}
+
+/// Whether the API server answers and every target has a ready pod, within `timeout`
+async fn healthy(client: Client, config: &ForwarderConfig, timeout: Duration) -> bool {
+    let check = async {
+        client.apiserver_version().await.ok()?;
+        for target in &config.targets {
+            let port = target.ports.first()?.remote;
+            resolve::backend(client.clone(), &target.namespace, &target.target, port)
+                .await
+                .ok()?;
+        }
+        Some(())
+    };
+    matches!(tokio::time::timeout(timeout, check).await, Ok(Some(())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Health;
+
+    #[test]
+    fn test_health_fails_over_and_back_after_thresholds() {
+        let mut health = Health::new(3);
+        assert_eq!(None, health.observe(&[false, true, true], 2, 3));
+        assert_eq!(Some((1, true)), health.observe(&[false, true, true], 2, 3));
+        assert_eq!(None, health.observe(&[true, true, true], 2, 3));
+        assert_eq!(None, health.observe(&[true, false, true], 2, 3));
+        assert_eq!(Some((0, false)), health.observe(&[true, true, true], 2, 3));
+    }
+
+    #[test]
+    fn test_health_stays_without_a_healthy_fallback() {
+        let mut health = Health::new(2);
+        assert_eq!(None, health.observe(&[false, false], 1, 1));
+        assert_eq!(0, health.active);
+        assert_eq!(Some((1, true)), health.observe(&[false, true], 1, 1));
+    }
+}
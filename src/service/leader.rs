@@ -0,0 +1,181 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Pod;
+use kube::{
+    runtime::{reflector, watcher},
+    Api, ResourceExt,
+};
+use tokio::{sync::watch, task::JoinHandle};
+
+use super::{failover::Clusters, resolve::is_ready};
+
+/// How long connections to a former leader stay open unless the target sets it
+const DEFAULT_DRAIN: Duration = Duration::from_secs(30);
+
+/// Name of the current leader pod, `None` while no ready pod matches
+pub(crate) type Current = watch::Receiver<Option<String>>;
+
+/// Picks the leader among the ready pods matching the labels. The current leader is
+/// kept while it still matches, so a relabeling that briefly leaves two pods matching
+/// does not move connections back and forth.
+pub(crate) fn elect<'a>(
+    current: Option<&str>,
+    ready: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    let mut ready: Vec<&str> = ready.into_iter().collect();
+    if current.is_some_and(|current| ready.contains(&current)) {
+        return current.map(str::to_owned);
+    }
+    ready.sort_unstable();
+    ready.first().map(|pod| pod.to_string())
+}
+
+/// Namespace and label selector of a `Leader` target
+type Labels = (String, String);
+
+/// A followed leader and the watch keeping it current
+type Following = (Current, JoinHandle<()>);
+
+/// Keeps a pod watch per cluster, namespace and label selector of `Leader` targets,
+/// shared by every listener forwarding to it
+pub(crate) struct Leaders {
+    client: Arc<Clusters>,
+    following: Mutex<HashMap<(usize, Labels), Following>>,
+}
+
+impl Leaders {
+    pub fn new(client: Arc<Clusters>) -> Self {
+        Self {
+            client,
+            following: Mutex::default(),
+        }
+    }
+
+    /// The leader among the pods matching `selector` in `cluster`, watched from the
+    /// first call on
+    pub fn follow(&self, cluster: usize, namespace: &str, selector: &str) -> Current {
+        let mut following = self.following.lock().unwrap();
+        let key = (cluster, (namespace.to_owned(), selector.to_owned()));
+        if let Some((current, handle)) = following.get(&key) {
+            if !handle.is_finished() {
+                return current.clone();
+            }
+        }
+        let (sender, current) = watch::channel(None);
+        let handle = tokio::spawn(watch_pods(
+            self.client.clone(),
+            cluster,
+            namespace.to_owned(),
+            selector.to_owned(),
+            sender,
+        ));
+        following.insert(key, (current.clone(), handle));
+        current
+    }
+
+    /// Stops the watches no listener forwards to anymore
+    pub fn retain(&self, keep: &[Labels]) {
+        self.following
+            .lock()
+            .unwrap()
+            .retain(|(_, labels), (_, handle)| {
+                let retained = keep.contains(labels);
+                if !retained {
+                    handle.abort();
+                }
+                retained
+            });
+    }
+}
+
+/// Follows the ready pods matching `selector` and publishes the elected leader. The
+/// watch restarts with the latest credentials after an error.
+async fn watch_pods(
+    client: Arc<Clusters>,
+    cluster: usize,
+    namespace: String,
+    selector: String,
+    leader: watch::Sender<Option<String>>,
+) {
+    loop {
+        let pods = Api::<Pod>::namespaced(client.client(cluster), &namespace);
+        let (reader, writer) = reflector::store();
+        let mut events = reflector(
+            writer,
+            watcher(pods, watcher::Config::default().labels(&selector)),
+        )
+        .boxed();
+        while let Some(event) = events.next().await {
+            if let Err(e) = event {
+                tracing::warn!("watching pods {} in {} failed: {}", selector, namespace, e);
+                break;
+            }
+            let ready: Vec<String> = reader
+                .state()
+                .iter()
+                .filter(|pod| is_ready(pod))
+                .map(|pod| pod.name_any())
+                .collect();
+            leader.send_if_modified(|current| {
+                let elected = elect(current.as_deref(), ready.iter().map(String::as_str));
+                let modified = *current != elected;
+                if modified {
+                    tracing::info!(
+                        "leader of pods {} in {} is now {}",
+                        selector,
+                        namespace,
+                        elected.as_deref().unwrap_or("unknown")
+                    );
+                    *current = elected;
+                }
+                modified
+            });
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Resolves once `pod` lost the lead and its drain period ran out
+pub(crate) async fn deposed(mut current: Current, pod: &str, drain: Option<u32>) {
+    loop {
+        if current.borrow_and_update().as_deref() != Some(pod) {
+            break;
+        }
+        if current.changed().await.is_err() {
+            // nobody follows the labels anymore, keep the connection
+            return futures::future::pending().await;
+        }
+    }
+    let drain = drain.map_or(DEFAULT_DRAIN, |s| Duration::from_secs(u64::from(s)));
+    tracing::info!(
+        "draining connections to former leader {} for {:?}",
+        pod,
+        drain
+    );
+    tokio::time::sleep(drain).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::elect;
+
+    #[test]
+    fn test_elect_keeps_current_leader() {
+        assert_eq!(None, elect(None, []));
+        assert_eq!(Some("db-1".to_owned()), elect(None, ["db-1"]));
+        assert_eq!(
+            Some("db-1".to_owned()),
+            elect(Some("db-1"), ["db-0", "db-1"])
+        );
+        assert_eq!(
+            Some("db-0".to_owned()),
+            elect(Some("db-1"), ["db-2", "db-0"])
+        );
+        assert_eq!(None, elect(Some("db-1"), []));
+    }
+}
@@ -1,70 +1,454 @@
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{routing::get, Json, Router};
+use axum_prometheus::PrometheusMetricLayer;
 use k8s_openapi::api::core::v1::Pod;
-use kube::{config::KubeConfigOptions, Api, Client, Config};
+use kube::{Api, Client};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    task::JoinHandle,
+};
+
+use crate::{
+    crd::{Target, TargetKind},
+    error::Error,
+};
+
+mod client;
+mod config;
+mod failover;
+mod leader;
+mod resolve;
+mod split;
+
+pub use self::client::CredentialFiles;
+pub(crate) use self::client::CredentialSource;
+pub(crate) use self::config::{ForwardTarget, ForwarderConfig, TargetSource};
+pub(crate) use self::failover::{CredentialPaths, FailoverCluster, FailoverConfig, FailoverStatus};
+pub(crate) use self::resolve::{is_ready, label_selector};
+pub(crate) use self::split::SplitConfig;
+use self::{
+    client::ReloadingClient,
+    failover::Clusters,
+    leader::{Current, Leaders},
+};
+
+/// A local port and the remote service port it is forwarded to, written as `local:remote`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct PortMapping {
+    pub local: u16,
+    pub remote: u16,
+}
 
-use crate::error::Error;
+impl std::fmt::Display for PortMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.local, self.remote)
+    }
+}
+
+impl From<PortMapping> for String {
+    fn from(mapping: PortMapping) -> Self {
+        mapping.to_string()
+    }
+}
+
+impl TryFrom<String> for PortMapping {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::str::FromStr for PortMapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |p: &str| {
+            p.parse::<u16>()
+                .map_err(|_| Error::InvalidPort(s.to_owned()))
+        };
+        match s.split_once(':') {
+            Some((local, remote)) => Ok(Self {
+                local: parse(local)?,
+                remote: parse(remote)?,
+            }),
+            None => {
+                let port = parse(s)?;
+                Ok(Self {
+                    local: port,
+                    remote: port,
+                })
+            }
+        }
+    }
+}
 
-pub(crate) struct ServiceOptions {
+/// The remote service a listener currently forwards to, swapped when the config changes
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Route {
     namespace: String,
-    name: String,
-    ports: Vec<u16>,
+    target: Target,
+    remote: u16,
+}
+
+pub(crate) async fn start(
+    targets: TargetSource,
     max_retries: Option<i32>,
+    credentials: CredentialSource,
+    failover: Option<FailoverConfig>,
+    split: Option<SplitConfig>,
+    reload_interval: Duration,
+    metrics_address: &str,
+) -> Result<(), Error> {
+    let metrics_address: SocketAddr = metrics_address
+        .parse()
+        .map_err(|e: std::net::AddrParseError| Error::Server(e.to_string()))?;
+    let primary = ReloadingClient::start(credentials, reload_interval).await?;
+    let client = Arc::new(match (&failover, &split) {
+        (Some(failover), _) => {
+            Clusters::start(
+                primary,
+                &failover.primary,
+                &failover.clusters,
+                reload_interval,
+            )
+            .await?
+        }
+        (None, Some(split)) => {
+            Clusters::start(primary, &split.primary, &split.clusters, reload_interval).await?
+        }
+        (None, None) => Clusters::single(primary),
+    });
+    if let Some(split) = split {
+        client.set_weights(&split::read_weights(&split.weights)?);
+        tokio::spawn(split::reload(
+            client.clone(),
+            split.weights,
+            reload_interval,
+        ));
+    }
+    let (_, metric_handle) = PrometheusMetricLayer::pair();
+    let mut metrics =
+        Router::new().route("/metrics", get(|| async move { metric_handle.render() }));
+    let (config, watched_config) = watch::channel(targets.read()?);
+    if let Some(failover) = failover {
+        let status = client.clone();
+        metrics = metrics.route("/failover", get(|| async move { Json(status.status()) }));
+        tokio::spawn(client.clone().monitor(failover, watched_config));
+    }
+    tokio::spawn(axum::Server::bind(&metrics_address).serve(metrics.into_make_service()));
+
+    let max_retries = max_retries.unwrap_or(3);
+    let mut listeners = Listeners::new(Leaders::new(client.clone()));
+    listeners
+        .apply(targets.read()?, &client, max_retries)
+        .await?;
+    if !targets.is_watched() {
+        return listeners.join().await;
+    }
+
+    let mut current = targets.read()?;
+    loop {
+        tokio::time::sleep(reload_interval).await;
+        let latest = match targets.read() {
+            Ok(latest) => latest,
+            Err(e) => {
+                tracing::warn!("config reload failed: {}", e);
+                continue;
+            }
+        };
+        if latest == current {
+            continue;
+        }
+        tracing::info!("reloading forwarder config");
+        if let Err(e) = listeners.apply(latest.clone(), &client, max_retries).await {
+            tracing::warn!("config reload failed: {}", e);
+        }
+        config.send_replace(latest.clone());
+        current = latest;
+    }
+}
+
+/// A running accept loop and the channel to reroute it
+type Listener = (watch::Sender<Route>, JoinHandle<Result<(), Error>>);
+
+/// Accept loops by local port
+struct Listeners {
+    running: HashMap<u16, Listener>,
+    leaders: Arc<Leaders>,
 }
 
-impl ServiceOptions {
-    pub fn new(namespace: String, name: String, ports: Vec<u16>, max_retries: Option<i32>) -> Self {
+/// Listens on every address of the pod. The IPv6 socket also accepts IPv4 connections,
+/// so dual-stack and IPv6-only services reach the forwarder. Nodes without IPv6 fall
+/// back to IPv4.
+async fn bind(port: u16) -> Result<TcpListener, Error> {
+    match TcpListener::bind((Ipv6Addr::UNSPECIFIED, port)).await {
+        Ok(listener) => Ok(listener),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => Err(Error::Server(e.to_string())),
+        Err(_) => TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+            .await
+            .map_err(|e| Error::Server(e.to_string())),
+    }
+}
+
+impl Listeners {
+    fn new(leaders: Leaders) -> Self {
         Self {
-            namespace,
-            name,
-            ports,
-            max_retries,
+            running: HashMap::new(),
+            leaders: Arc::new(leaders),
+        }
+    }
+
+    /// Binds new local ports, reroutes changed ones and closes removed ones. Open
+    /// connections are not interrupted.
+    async fn apply(
+        &mut self,
+        config: ForwarderConfig,
+        client: &Arc<Clusters>,
+        max_retries: i32,
+    ) -> Result<(), Error> {
+        let mut routes = HashMap::new();
+        for target in config.targets {
+            for port in target.ports {
+                let route = Route {
+                    namespace: target.namespace.clone(),
+                    target: target.target.clone(),
+                    remote: port.remote,
+                };
+                if routes.insert(port.local, route).is_some() {
+                    return Err(Error::InvalidPort(format!(
+                        "local port {} is used twice",
+                        port.local
+                    )));
+                }
+            }
+        }
+
+        let followed: Vec<(String, String)> = routes
+            .values()
+            .filter(|route| route.target.kind == TargetKind::Leader)
+            .map(|route| (route.namespace.clone(), route.target.selector()))
+            .collect();
+        self.leaders.retain(&followed);
+        self.running.retain(|port, (_, handle)| {
+            let keep = routes.contains_key(port);
+            if !keep {
+                tracing::info!("closing :{}", port);
+                handle.abort();
+            }
+            keep
+        });
+        for (port, route) in routes {
+            if let Some((sender, _)) = self.running.get(&port) {
+                sender.send_if_modified(|current| {
+                    let modified = *current != route;
+                    if modified {
+                        tracing::info!(
+                            "forwarding :{} to {} in {}:{}",
+                            port,
+                            &route.target,
+                            &route.namespace,
+                            route.remote
+                        );
+                        *current = route.clone();
+                    }
+                    modified
+                });
+                continue;
+            }
+
+            let listener = bind(port).await?;
+            tracing::info!(
+                "forwarding :{} to {} in {}:{}",
+                port,
+                &route.target,
+                &route.namespace,
+                route.remote
+            );
+            let (sender, receiver) = watch::channel(route);
+            let handle = tokio::spawn(accept(
+                listener,
+                receiver,
+                client.clone(),
+                self.leaders.clone(),
+                max_retries,
+            ));
+            self.running.insert(port, (sender, handle));
+        }
+        Ok(())
+    }
+
+    async fn join(self) -> Result<(), Error> {
+        let handles = self.running.into_values().map(|(_, handle)| handle);
+        for listener in futures::future::join_all(handles).await {
+            listener.map_err(|e| Error::Server(e.to_string()))??;
         }
+        Ok(())
     }
 }
 
-pub(crate) async fn start(
-    service_options: ServiceOptions,
-    kube_options: &KubeConfigOptions,
+async fn accept(
+    listener: TcpListener,
+    route: watch::Receiver<Route>,
+    client: Arc<Clusters>,
+    leaders: Arc<Leaders>,
+    max_retries: i32,
 ) -> Result<(), Error> {
-    let max_retries = service_options.max_retries.unwrap_or(3);
-    let cfg = Config::from_kubeconfig(kube_options).await?;
+    loop {
+        let (socket, peer) = listener
+            .accept()
+            .await
+            .map_err(|e| Error::Server(e.to_string()))?;
+        let cluster = client.pick();
+        let name = client.name(cluster).to_owned();
+        let client = client.client(cluster);
+        let route = route.borrow().clone();
+        let leader = (route.target.kind == TargetKind::Leader)
+            .then(|| leaders.follow(cluster, &route.namespace, &route.target.selector()));
+        metrics::increment_counter!("forwarder_connections_total", "cluster" => name.clone());
+        metrics::increment_gauge!("forwarder_open_connections", 1.0, "cluster" => name.clone());
+        tokio::spawn(async move {
+            let forwarded = forward(client, &route, leader, max_retries, socket).await;
+            metrics::decrement_gauge!("forwarder_open_connections", 1.0, "cluster" => name);
+            if let Err(e) = forwarded {
+                tracing::warn!(
+                    "forwarding {} to {} in {} failed: {}",
+                    peer,
+                    &route.target,
+                    &route.namespace,
+                    e
+                );
+            }
+        });
+    }
+}
 
-    let client = Client::try_from(cfg).unwrap();
-    let api = Api::<Pod>::namespaced(client, &service_options.namespace);
+async fn forward(
+    client: Client,
+    route: &Route,
+    leader: Option<Current>,
+    max_retries: i32,
+    mut socket: TcpStream,
+) -> Result<(), Error> {
+    let pods = Api::<Pod>::namespaced(client.clone(), &route.namespace);
 
     let mut attempts = 1;
-    loop {
+    let (mut forwarder, backend) = loop {
         if attempts > max_retries {
             return Err(Error::MaxAttempts(max_retries));
         }
 
-        match api
-            .portforward(&service_options.name, &service_options.ports)
-            .await
-        {
-            Ok(pf) => {
-                attempts = 1;
-                if let Err(e) = pf.join().await {
-                    tracing::warn!(
-                        "join error {}/{}: {}",
-                        &service_options.namespace,
-                        &service_options.name,
-                        e
-                    );
-                } else {
-                    tracing::info!("port forwarding complete");
-                    return Ok(());
-                }
+        let backend = match &leader {
+            Some(current) => current
+                .borrow()
+                .clone()
+                .map(|pod| resolve::Backend {
+                    pod,
+                    port: route.remote,
+                })
+                .ok_or_else(|| Error::InvalidService {
+                    name: route.target.to_string(),
+                    message: "no ready pod leads".to_owned(),
+                }),
+            None => {
+                resolve::backend(
+                    client.clone(),
+                    &route.namespace,
+                    &route.target,
+                    route.remote,
+                )
+                .await
             }
+        };
+        let result = match backend {
+            Ok(backend) => pods
+                .portforward(&backend.pod, &[backend.port])
+                .await
+                .map(|pf| (pf, backend))
+                .map_err(|e| Error::Kubernetes { source: e }),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(established) => break established,
             Err(e) => {
                 attempts += 1;
                 tracing::warn!(
-                    "unable to start port forward to service {}/{}: {}",
-                    &service_options.namespace,
-                    &service_options.name,
+                    "unable to start port forward to {} in {}: {}",
+                    &route.target,
+                    &route.namespace,
                     e
                 );
-                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await
+                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await
             }
         }
+    };
+
+    let mut upstream = forwarder
+        .take_stream(backend.port)
+        .ok_or_else(|| Error::Server(format!("no stream for port {}", backend.port)))?;
+    let copy = tokio::io::copy_bidirectional(&mut socket, &mut upstream);
+    let copied = match leader {
+        // connections to a former leader are closed once drained, so clients
+        // reconnect to the new one
+        Some(current) => tokio::select! {
+            copied = copy => copied,
+            _ = leader::deposed(current, &backend.pod, route.target.drain_seconds) => {
+                tracing::info!("closing connection to former leader {}", &backend.pod);
+                Ok((0, 0))
+            }
+        },
+        None => copy.await,
+    };
+    if let Err(e) = copied {
+        tracing::debug!("connection to {} closed: {}", &backend.pod, e);
+    }
+    drop(upstream);
+    forwarder
+        .join()
+        .await
+        .map_err(|e| Error::Server(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PortMapping;
+
+    #[test]
+    fn test_port_mapping_with_remote() {
+        let mapping: PortMapping = "8080:80".parse().unwrap();
+        assert_eq!(
+            PortMapping {
+                local: 8080,
+                remote: 80
+            },
+            mapping
+        );
+    }
+
+    #[test]
+    fn test_port_mapping_single_port() {
+        let mapping: PortMapping = "5432".parse().unwrap();
+        assert_eq!(
+            PortMapping {
+                local: 5432,
+                remote: 5432
+            },
+            mapping
+        );
+    }
+
+    #[test]
+    fn test_port_mapping_invalid() {
+        assert!("http".parse::<PortMapping>().is_err());
+        assert!(":80".parse::<PortMapping>().is_err());
     }
 }
@@ -0,0 +1,205 @@
+use k8s_openapi::{
+    api::{
+        apps::v1::{Deployment, StatefulSet},
+        core::v1::{Pod, Service},
+    },
+    apimachinery::pkg::{apis::meta::v1::LabelSelector, util::intstr::IntOrString},
+};
+use kube::{api::ListParams, Api, Client, ResourceExt};
+
+use super::leader::elect;
+use crate::{
+    crd::{Target, TargetKind},
+    error::Error,
+};
+
+/// A pod and container port that a connection can be tunneled to
+pub(crate) struct Backend {
+    pub pod: String,
+    pub port: u16,
+}
+
+pub(crate) fn is_ready(pod: &Pod) -> bool {
+    pod.metadata.deletion_timestamp.is_none()
+        && pod
+            .status
+            .as_ref()
+            .and_then(|s| s.conditions.as_ref())
+            .map(|c| c.iter().any(|c| c.type_ == "Ready" && c.status == "True"))
+            .unwrap_or_default()
+}
+
+/// Label selector in the syntax of list requests
+pub(crate) fn label_selector(selector: &LabelSelector) -> String {
+    let labels = selector
+        .match_labels
+        .iter()
+        .flatten()
+        .map(|(k, v)| format!("{k}={v}"));
+    let expressions = selector.match_expressions.iter().flatten().map(|e| {
+        let values = e.values.clone().unwrap_or_default().join(",");
+        match e.operator.as_str() {
+            "In" => format!("{} in ({values})", e.key),
+            "NotIn" => format!("{} notin ({values})", e.key),
+            "DoesNotExist" => format!("!{}", e.key),
+            _ => e.key.clone(),
+        }
+    });
+    labels.chain(expressions).collect::<Vec<_>>().join(",")
+}
+
+/// Picks a ready pod for a connection to `port` of the target
+pub(crate) async fn backend(
+    client: Client,
+    namespace: &str,
+    target: &Target,
+    port: u16,
+) -> Result<Backend, Error> {
+    let invalid = |message: String| Error::InvalidService {
+        name: target.to_string(),
+        message,
+    };
+    target.validate().map_err(invalid)?;
+    let name = target.name.as_deref().unwrap_or_default();
+    let selector = match target.kind {
+        TargetKind::Service => return service_backend(client, namespace, name, port).await,
+        TargetKind::Pod => {
+            let pod = Api::<Pod>::namespaced(client, namespace)
+                .get(name)
+                .await
+                .map_err(|e| Error::Kubernetes { source: e })?;
+            if !is_ready(&pod) {
+                return Err(invalid("pod is not ready".to_owned()));
+            }
+            return Ok(Backend {
+                pod: pod.name_any(),
+                port,
+            });
+        }
+        TargetKind::Deployment => Api::<Deployment>::namespaced(client.clone(), namespace)
+            .get(name)
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?
+            .spec
+            .map(|spec| label_selector(&spec.selector)),
+        TargetKind::StatefulSet => Api::<StatefulSet>::namespaced(client.clone(), namespace)
+            .get(name)
+            .await
+            .map_err(|e| Error::Kubernetes { source: e })?
+            .spec
+            .map(|spec| label_selector(&spec.selector)),
+        TargetKind::Selector | TargetKind::Leader => Some(target.selector()),
+    }
+    .filter(|s| !s.is_empty())
+    .ok_or_else(|| invalid("no pod selector".to_owned()))?;
+
+    let ready: Vec<String> = Api::<Pod>::namespaced(client, namespace)
+        .list(&ListParams::default().labels(&selector))
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?
+        .into_iter()
+        .filter(is_ready)
+        .map(|pod| pod.name_any())
+        .collect();
+    let pod = match target.kind {
+        TargetKind::Leader => elect(None, ready.iter().map(String::as_str)),
+        _ => ready.into_iter().next(),
+    }
+    .ok_or_else(|| invalid("no ready pods".to_owned()))?;
+    Ok(Backend { pod, port })
+}
+
+fn container_port(pod: &Pod, name: &str) -> Option<u16> {
+    pod.spec
+        .as_ref()?
+        .containers
+        .iter()
+        .flat_map(|c| c.ports.iter().flatten())
+        .find(|p| p.name.as_deref() == Some(name))
+        .and_then(|p| u16::try_from(p.container_port).ok())
+}
+
+/// Picks a ready pod behind a service, the same way `kubectl port-forward svc/...` does
+async fn service_backend(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    port: u16,
+) -> Result<Backend, Error> {
+    let invalid = |message: &str| Error::InvalidService {
+        name: name.to_owned(),
+        message: message.to_owned(),
+    };
+    let services: Api<Service> = Api::namespaced(client.clone(), namespace);
+    let service = services
+        .get(name)
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?;
+    let spec = service.spec.unwrap_or_default();
+    let selector = spec
+        .selector
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| invalid("service has no selector"))?
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let target_port = spec
+        .ports
+        .unwrap_or_default()
+        .into_iter()
+        .find(|p| p.port == i32::from(port))
+        .and_then(|p| p.target_port);
+
+    let pods: Api<Pod> = Api::namespaced(client, namespace);
+    let pod = pods
+        .list(&ListParams::default().labels(&selector))
+        .await
+        .map_err(|e| Error::Kubernetes { source: e })?
+        .into_iter()
+        .find(is_ready)
+        .ok_or_else(|| invalid("no ready pods"))?;
+
+    let port = match target_port {
+        Some(IntOrString::Int(p)) => u16::try_from(p).map_err(|_| invalid("invalid port"))?,
+        Some(IntOrString::String(named)) => container_port(&pod, &named)
+            .ok_or_else(|| invalid(&format!("pod has no port named {named}")))?,
+        None => port,
+    };
+    Ok(Backend {
+        pod: pod.name_any(),
+        port,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement};
+
+    use super::label_selector;
+
+    #[test]
+    fn test_label_selector_with_expressions() {
+        let selector = LabelSelector {
+            match_labels: Some(BTreeMap::from([("app".to_owned(), "db".to_owned())])),
+            match_expressions: Some(vec![
+                LabelSelectorRequirement {
+                    key: "tier".to_owned(),
+                    operator: "In".to_owned(),
+                    values: Some(vec!["primary".to_owned(), "replica".to_owned()]),
+                },
+                LabelSelectorRequirement {
+                    key: "canary".to_owned(),
+                    operator: "DoesNotExist".to_owned(),
+                    values: None,
+                },
+            ]),
+        };
+        assert_eq!(
+            "app=db,tier in (primary,replica),!canary",
+            label_selector(&selector)
+        );
+    }
+}
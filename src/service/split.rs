@@ -0,0 +1,93 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::{failover::Clusters, FailoverCluster};
+use crate::error::Error;
+
+/// Clusters sharing the new connections of the forwarder by weight
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SplitConfig {
+    /// Name of the cluster of the forwarder's own credentials
+    pub primary: String,
+    pub clusters: Vec<FailoverCluster>,
+    /// JSON file with the weight of every cluster by name, reloaded when it changes
+    pub weights: String,
+}
+
+/// Smooth weighted round robin, so that any run of connections follows the weights
+/// closely instead of arriving in bursts per cluster
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Split {
+    weights: Vec<u32>,
+    current: Vec<i64>,
+}
+
+impl Split {
+    pub fn new(weights: Vec<u32>) -> Self {
+        Self {
+            current: vec![0; weights.len()],
+            weights,
+        }
+    }
+
+    pub fn weights(&self) -> &[u32] {
+        &self.weights
+    }
+
+    /// Cluster of the next connection, `None` when every weight is zero
+    pub fn next(&mut self) -> Option<usize> {
+        let total: i64 = self.weights.iter().map(|w| i64::from(*w)).sum();
+        if total == 0 {
+            return None;
+        }
+        for (current, weight) in self.current.iter_mut().zip(&self.weights) {
+            *current += i64::from(*weight);
+        }
+        let (next, _) = self
+            .current
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, current)| **current)?;
+        self.current[next] -= total;
+        Some(next)
+    }
+}
+
+pub(crate) fn read_weights(path: &str) -> Result<BTreeMap<String, u32>, Error> {
+    let contents =
+        std::fs::read(path).map_err(|e| Error::Server(format!("unable to read {path}: {e}")))?;
+    serde_json::from_slice(&contents)
+        .map_err(|e| Error::Server(format!("invalid weights {path}: {e}")))
+}
+
+/// Applies changes of the weights file, so weights are adjusted without a restart
+pub(crate) async fn reload(clusters: Arc<Clusters>, path: String, interval: Duration) {
+    let mut current = None;
+    loop {
+        match read_weights(&path) {
+            Ok(weights) if current.as_ref() != Some(&weights) => {
+                tracing::info!("splitting new connections by weight {:?}", weights);
+                clusters.set_weights(&weights);
+                current = Some(weights);
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("weights reload failed: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Split;
+
+    #[test]
+    fn test_split_follows_weights() {
+        let mut split = Split::new(vec![3, 1, 0]);
+        let picks: Vec<usize> = (0..8).filter_map(|_| split.next()).collect();
+        assert_eq!(vec![0, 0, 1, 0, 0, 0, 1, 0], picks);
+        assert_eq!(None, Split::new(vec![0, 0]).next());
+    }
+}